@@ -0,0 +1,151 @@
+use std::cmp::min;
+use std::collections::HashMap;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use rug::Integer;
+
+use MPQS::polynomial::{Family, Polynomial};
+use MPQS::serial_MPQS::{initialize_qs, InitResult};
+use MPQS::sieve::{
+    candidates, sieve_block, Buckets, Patterns, PrimePower, SieveRoots, BLOCK_SIZE, NO_ROOT,
+};
+
+/// Offsets of the roots of `polynomial` at the start of the interval
+fn starts(polynomial: &Polynomial, factorbase: &[u64], xmax: i64) -> HashMap<u64, (i64, i64)> {
+    factorbase
+        .iter()
+        .zip(&polynomial.roots)
+        .filter(|(_, (sol1, _))| *sol1 != NO_ROOT)
+        .map(|(p, (sol1, sol2))| {
+            let start = |sol: u32| ((sol as u64 + xmax as u64) % p) as i64;
+            (*p, (start(*sol1), start(*sol2)))
+        })
+        .collect()
+}
+
+/// The sieve as it was before the byte logarithms and the dense roots:
+/// `f64` logarithms, a fresh block every time and roots in hash maps
+fn sieve_f64(
+    factorbase: &[u64],
+    starts: &HashMap<u64, (i64, i64)>,
+    xmax: i64,
+    min_prime: u64,
+    thresh: f64,
+) -> usize {
+    let tlog: Vec<f64> = factorbase.iter().map(|p| (*p as f64).log10()).collect();
+    let mut s1: HashMap<u64, i64> = starts.iter().map(|(p, s)| (*p, s.0)).collect();
+    let mut s2: HashMap<u64, i64> = starts.iter().map(|(p, s)| (*p, s.1)).collect();
+    let sievesize = 1_i64 << 15;
+    let mut hits = 0;
+
+    for low in (-xmax..xmax + 1).step_by(sievesize as usize + 1) {
+        let high = min(xmax, low + sievesize);
+        let size = high - low;
+        let size_plus_1 = size + 1;
+
+        let mut sieve = vec![0_f64; size_plus_1 as usize];
+
+        for (i, p) in factorbase.iter().enumerate() {
+            if *p < min_prime || !s1.contains_key(p) {
+                continue;
+            }
+            let mut sol1 = s1[p];
+            let mut sol2 = s2[p];
+            let logp = tlog[i];
+
+            let p_i64 = *p as i64;
+            while sol1 <= size || sol2 <= size {
+                if sol1 <= size {
+                    sieve[sol1 as usize] += logp;
+                    sol1 += p_i64;
+                }
+                if sol2 <= size {
+                    sieve[sol2 as usize] += logp;
+                    sol2 += p_i64;
+                }
+            }
+            s1.insert(*p, sol1 - size_plus_1);
+            s2.insert(*p, sol2 - size_plus_1);
+        }
+        hits += sieve.iter().filter(|v| **v > thresh).count();
+    }
+    hits
+}
+
+fn sieve_u8(
+    factorbase: &[u64],
+    tlog: &[u8],
+    powers: &[PrimePower],
+    polynomial: &Polynomial,
+    xmax: i64,
+    thresh: u8,
+) -> usize {
+    let mut roots = SieveRoots::new(polynomial, factorbase, tlog, powers, xmax);
+    let patterns = Patterns::new(&roots, factorbase, tlog);
+    let buckets = Buckets::new(&roots, factorbase, 2 * xmax as usize + 1);
+    let mut block = vec![0_u8; BLOCK_SIZE];
+    let mut hits = 0;
+
+    for (k, low) in (-xmax..xmax + 1).step_by(BLOCK_SIZE).enumerate() {
+        let size = min(BLOCK_SIZE as i64, xmax + 1 - low) as usize;
+        let block = &mut block[..size];
+        let start = k * BLOCK_SIZE;
+        sieve_block(
+            block,
+            start,
+            factorbase,
+            tlog,
+            &mut roots,
+            &patterns,
+            buckets.block(k),
+        );
+        hits += candidates(block, thresh).count();
+    }
+    hits
+}
+
+fn bench_sieve(c: &mut Criterion) {
+    let n = "523022617466601111760007224100074291200000001"
+        .parse::<Integer>()
+        .unwrap();
+    let InitResult {
+        context,
+        mut seeder,
+    } = initialize_qs(&n).unwrap();
+    let seed = seeder.next_seed(&context.kn, &context.factorbase);
+    let polynomial = Family::new(&context.kn, &context.factorbase, &context.tsqrt, seed)
+        .next()
+        .unwrap();
+    let starts = starts(&polynomial, &context.factorbase, context.xmax);
+    let thresh = context.thresh as f64 / std::f64::consts::LOG2_10;
+    let min_prime = (thresh * 3_f64) as u64;
+
+    let mut group = c.benchmark_group("sieve interval");
+    group.bench_function("f64 logarithms, hashed roots", |b| {
+        b.iter(|| {
+            sieve_f64(
+                &context.factorbase,
+                &starts,
+                context.xmax,
+                min_prime,
+                thresh,
+            )
+        })
+    });
+    group.bench_function("u8 logarithms, dense roots", |b| {
+        b.iter(|| {
+            sieve_u8(
+                &context.factorbase,
+                &context.tlog,
+                &context.powers,
+                &polynomial,
+                context.xmax,
+                context.thresh,
+            )
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_sieve);
+criterion_main!(benches);
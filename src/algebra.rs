@@ -1,16 +1,31 @@
+use std::collections::HashSet;
+use std::time::Instant;
+
 use rug::Integer;
 use rug::ops::Pow;
 
+use crate::lanczos;
+use crate::relation::Relation;
+use crate::report::Timings;
+
+/// Relations gathered beyond the size of the factor base before the linear algebra is tried:
+/// the sign takes one row of the matrix and the others leave dependencies to spare
+pub const RELATION_EXCESS: usize = 10;
+
+/// Matrices with at least this many relations are solved with Block Lanczos
+/// instead of Gaussian elimination. On random matrices with 18 entries per column,
+/// elimination on `Integer` bitsets takes 34ms for 500 columns and 0.7s for 2000,
+/// Block Lanczos 2ms and 18ms, but with 64-bit blocks it often finds nothing below 100 columns
+const LANCZOS_THRESHOLD: usize = 500;
+
+/// Looks for a factor of `n` among the dependencies of `smooths`, adding the time of every step to `timings`
 pub fn algebra(
     factorbase: &[u64],
-    smooths: &[(Integer, (Integer, Integer))],
+    smooths: &[Relation],
     settings: &Integer,
+    timings: &mut Timings,
 ) -> Option<Integer> {
     let n = settings;
-    let mut m_vector: Vec<Integer> = smooths
-        .iter()
-        .map(|(_, (el, _))| create_vector(el, &factorbase))
-        .collect();
 
     let factorbase_new = {
         let mut temp = vec![Integer::from(-1)];
@@ -18,44 +33,77 @@ pub fn algebra(
         temp
     };
 
-    let mut h_vector: Vec<Integer> = (0..m_vector.len())
-        .map(|i| Integer::from(1) << i as u32)
-        .collect();
+    let start = Instant::now();
+    let rows: Vec<Vec<u32>> = smooths.iter().map(Relation::rows).collect();
+    let kept = filter(smooths, &rows, factorbase_new.len());
+    let columns: Vec<Vec<u32>> = kept.iter().map(|i| rows[*i].clone()).collect();
+    timings.filtering += start.elapsed();
 
-    reduce_row_echelon_form(&mut m_vector, &mut h_vector, factorbase_new.len());
+    let start = Instant::now();
+    let dependencies = if columns.len() < LANCZOS_THRESHOLD {
+        gaussian_dependencies(factorbase, &columns)
+    } else {
+        lanczos::find_dependencies(&columns, factorbase_new.len())
+            .unwrap_or_else(|| gaussian_dependencies(factorbase, &columns))
+    };
+    timings.linear_algebra += start.elapsed();
 
-    let nul_cols: Vec<Integer> = h_vector
-        .into_iter()
-        .enumerate()
-        .filter(|(i, _)| m_vector[*i] == 0)
-        .map(|(_, e)| e)
+    let start = Instant::now();
+    let factor = square_root(n, &factorbase_new, smooths, &kept, dependencies);
+    timings.square_root += start.elapsed();
+    factor
+}
+
+/// Indices of the relations that can be part of a dependency: duplicates are dropped,
+/// `lhs` and `-lhs` having the same square, and so are the relations with an odd exponent
+/// of a prime that no other relation has
+fn filter(smooths: &[Relation], rows: &[Vec<u32>], nrows: usize) -> Vec<usize> {
+    let mut seen = HashSet::new();
+    let mut kept: Vec<usize> = (0..smooths.len())
+        .filter(|i| seen.insert(smooths[*i].lhs.clone().abs()))
         .collect();
 
-    for nc in nul_cols {
+    loop {
+        let mut count = vec![0_usize; nrows];
+        for row in kept.iter().flat_map(|i| &rows[*i]) {
+            count[*row as usize] += 1;
+        }
+        let before = kept.len();
+        kept.retain(|i| rows[*i].iter().all(|row| count[*row as usize] > 1));
+        if kept.len() == before {
+            return kept;
+        }
+    }
+}
+
+/// Gcd of `n` with the difference of the square roots of the two sides of every dependency
+/// of the relations `kept`, until one of them is a nontrivial factor
+fn square_root(
+    n: &Integer,
+    factorbase_new: &[Integer],
+    smooths: &[Relation],
+    kept: &[usize],
+    dependencies: Vec<Vec<usize>>,
+) -> Option<Integer> {
+    for dependency in dependencies {
         let mut lhs = Integer::from(1);
-        let mut rhs = vec![Integer::new(); factorbase_new.len()];
+        let mut rhs = vec![0_u32; factorbase_new.len()];
         let mut rhspr = Integer::from(1);
 
-        for (index, (lh, (rh, ra))) in smooths.iter().enumerate() {
-            if (Integer::from(1) << index as u32) & &nc > 0 {
-                lhs *= lh;
-                rhspr *= ra;
-                if *rh < 0 {
-                    rhs[0] += 1;
-                }
-                let mut rh = rh.clone();
-                for j in 1..factorbase_new.len() {
-                    while rh.is_divisible(&factorbase_new[j]) {
-                        rh /= &factorbase_new[j];
-                        rhs[j] += 1;
-                    }
-                }
+        for relation in dependency.into_iter().map(|index| &smooths[kept[index]]) {
+            lhs *= &relation.lhs;
+            rhspr *= &relation.ra;
+            if relation.negative {
+                rhs[0] += 1;
+            }
+            for (i, e) in relation.factors.iter() {
+                rhs[i + 1] += e;
             }
         }
         for (j, factor) in factorbase_new.iter().enumerate() {
-            rhspr *= factor.clone().pow(rhs[j].to_u32().unwrap() >> 1);
+            rhspr *= factor.clone().pow(rhs[j] >> 1);
         }
-        let g = Integer::from(rhspr - lhs).gcd(n);
+        let g = (rhspr - lhs).gcd(n);
         if g != 1 && g != *n {
             return Some(g);
         }
@@ -63,30 +111,42 @@ pub fn algebra(
     None
 }
 
-fn create_vector(n: &Integer, factor_base: &[u64]) -> Integer {
-    let mut n = n.clone();
-    let mut a = Integer::new();
-    let lg = factor_base.len() - 1;
-    if n < 0 {
-        a |= Integer::from(2) << lg as u32;
-        n = -n;
-    }
-    for (i, p) in factor_base.iter().enumerate() {
-        if n.clone() % p == 0 {
-            let mut c = 0;
-            while n.clone() % p == 0 {
-                n /= p;
-                c += 1;
-            }
-            if c & 1 > 0 {
-                a |= Integer::from(1) << (lg - i) as u32;
+fn gaussian_dependencies(factorbase: &[u64], columns: &[Vec<u32>]) -> Vec<Vec<usize>> {
+    let mut m_vector: Vec<Integer> = columns
+        .iter()
+        .map(|rows| create_vector(rows, factorbase.len()))
+        .collect();
+
+    let mut h_vector: Vec<Integer> = (0..m_vector.len())
+        .map(|i| Integer::from(1) << i as u32)
+        .collect();
+
+    reduce_row_echelon_form(&mut m_vector, &mut h_vector, factorbase.len() + 1);
+
+    h_vector
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| m_vector[*i] == 0)
+        .map(|(_, h)| {
+            let mut dependency = Vec::new();
+            let mut next = h.find_one(0);
+            while let Some(index) = next {
+                dependency.push(index as usize);
+                next = h.find_one(index + 1);
             }
-        }
-    }
-    a
+            dependency
+        })
+        .collect()
+}
+
+/// Bit `factorbase_len` is the sign, bit `factorbase_len - 1 - i` is `factorbase[i]`
+fn create_vector(rows: &[u32], factorbase_len: usize) -> Integer {
+    rows.iter().fold(Integer::new(), |a, row| {
+        a | Integer::from(1) << (factorbase_len - *row as usize) as u32
+    })
 }
 
-fn reduce_row_echelon_form(m: &mut Vec<Integer>, h: &mut Vec<Integer>, column_count: usize) {
+fn reduce_row_echelon_form(m: &mut [Integer], h: &mut [Integer], column_count: usize) {
     if m.is_empty() {
         return;
     }
@@ -121,3 +181,30 @@ fn reduce_row_echelon_form(m: &mut Vec<Integer>, h: &mut Vec<Integer>, column_co
         lead += 1;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use rug::Integer;
+
+    use super::*;
+
+    #[test]
+    fn test_filter() {
+        let relation = |lhs: i64, factors: Vec<(usize, u32)>| Relation {
+            lhs: Integer::from(lhs),
+            negative: false,
+            factors,
+            large_primes: (Integer::from(1), Integer::from(1)),
+            ra: Integer::from(1),
+            origins: Vec::new(),
+        };
+        let smooths = vec![
+            relation(5, vec![(0, 1)]),
+            relation(-5, vec![(0, 1)]),
+            relation(7, vec![(0, 1), (1, 2)]),
+            relation(11, vec![(2, 1)]),
+        ];
+        let rows: Vec<Vec<u32>> = smooths.iter().map(Relation::rows).collect();
+        assert_eq!(filter(&smooths, &rows, 4), vec![0, 2]);
+    }
+}
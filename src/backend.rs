@@ -0,0 +1,182 @@
+use std::sync::Arc;
+use std::thread::ScopedJoinHandle;
+use std::time::{Duration, Instant};
+
+use rug::Integer;
+
+use crate::algebra;
+use crate::control::Control;
+use crate::error::FactorError;
+use crate::kernel::{SieveContext, Sieved};
+use crate::large_primes::LargePrimeGraph;
+use crate::polynomial::PolynomialSeeder;
+use crate::preprocess::perfect_power;
+use crate::relation::Relation;
+use crate::report::{Progress, RunReport, Timings};
+use crate::serial_MPQS::{factor_base_divisor, InitResult};
+
+/// How the polynomials and the relations are spread over the threads of a run,
+/// the sieving itself is left to `SieveKernel`
+pub trait Backend {
+    /// Sieves the polynomials handed out by `seeder` until the relations give a factor of `n`
+    /// or `control` stops the run
+    fn sieve(
+        &self,
+        n: &Integer,
+        context: Arc<SieveContext>,
+        seeder: PolynomialSeeder,
+        control: &Control,
+    ) -> Result<(Integer, RunReport), FactorError>;
+}
+
+/// Runs `backend` with the settings in `init`, a factor base prime dividing `n`
+/// or the root of a perfect power is returned without sieving
+pub fn run(
+    backend: &dyn Backend,
+    n: &Integer,
+    init: InitResult,
+    control: &Control,
+) -> Result<(Integer, RunReport), FactorError> {
+    if let Some(p) = factor_base_divisor(n, &init.context.factorbase) {
+        return Ok((p, RunReport::default()));
+    }
+    if let Some((root, _)) = perfect_power(n) {
+        return Ok((root, RunReport::default()));
+    }
+    let start = Instant::now();
+    let InitResult { context, seeder } = init;
+    let (factor, mut report) = backend.sieve(n, Arc::new(context), seeder, control)?;
+    report.timings.sieve = start.elapsed().saturating_sub(report.timings.algebra());
+    Ok((factor, report))
+}
+
+/// Waits for the workers, which must have been told to stop,
+/// a worker that panicked turns a run without a factor into `WorkerFailure`
+pub(crate) fn join<T>(
+    workers: Vec<ScopedJoinHandle<()>>,
+    factor: Result<T, FactorError>,
+) -> Result<T, FactorError> {
+    let mut failed = false;
+    for worker in workers {
+        failed |= worker.join().is_err();
+    }
+    match factor {
+        Err(_) if failed => Err(FactorError::WorkerFailure),
+        factor => factor,
+    }
+}
+
+/// Smooth relations gathered so far, with the partials waiting for a cycle
+pub struct Relations {
+    pub smooths: Vec<Relation>,
+    pub partials: LargePrimeGraph,
+    /// Smooth relations needed before the linear algebra is tried
+    pub target: usize,
+    pub polynomials: usize,
+    /// Smooth relations combined from partials
+    pub combined: usize,
+    /// Time spent in the linear algebra so far, the sieve time is left to the caller
+    pub timings: Timings,
+}
+
+impl Relations {
+    /// Store for a run over `factorbase`, the linear algebra needs `RELATION_EXCESS` more relations than primes
+    pub fn new(factorbase: &[u64]) -> Relations {
+        Relations {
+            smooths: Vec::new(),
+            partials: LargePrimeGraph::new(),
+            target: factorbase.len() + algebra::RELATION_EXCESS,
+            polynomials: 0,
+            combined: 0,
+            timings: Timings::default(),
+        }
+    }
+
+    /// Adds the output of the sieve, partials that close a cycle become smooth relations
+    pub fn add(&mut self, sieved: Sieved) {
+        let Sieved {
+            mut smooths,
+            partials,
+            stats,
+            polynomials,
+        } = sieved;
+        self.polynomials += polynomials;
+        self.smooths.append(&mut smooths);
+        self.partials.stats.merge(&stats);
+        for relation in partials {
+            if let Some(relation) = self.partials.add(relation) {
+                self.smooths.push(relation);
+                self.combined += 1;
+            }
+        }
+    }
+
+    /// Looks for a factor of `n` once there are `target` relations
+    pub fn factor(&mut self, n: &Integer, factorbase: &[u64]) -> Option<Integer> {
+        if self.smooths.len() < self.target {
+            return None;
+        }
+        algebra::algebra(factorbase, &self.smooths, n, &mut self.timings)
+    }
+
+    /// Counts of the relations after `elapsed`
+    pub fn progress(&self, elapsed: Duration) -> Progress {
+        Progress {
+            polynomials: self.polynomials,
+            full: self.smooths.len() - self.combined,
+            combined: self.combined,
+            partials: self.partials.len(),
+            target: self.target,
+            cofactors: self.partials.stats,
+            elapsed,
+        }
+    }
+
+    /// Report of a run that found a factor after `elapsed`
+    pub fn report(&self, elapsed: Duration) -> RunReport {
+        RunReport {
+            progress: self.progress(elapsed),
+            timings: self.timings,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rug::Integer;
+
+    use super::*;
+
+    #[test]
+    fn test_relations() {
+        let relation = |i: usize, q: u64| Relation {
+            lhs: Integer::from(i),
+            negative: false,
+            factors: vec![(i, 1)],
+            large_primes: (Integer::from(1), Integer::from(q)),
+            ra: Integer::from(1),
+            origins: Vec::new(),
+        };
+        let mut relations = Relations::new(&[2, 3, 5, 7]);
+        let mut sieved = Sieved::default();
+        sieved.smooths.push(relation(2, 1));
+        sieved.partials.push(relation(3, 101));
+        sieved.stats.too_large = 4;
+        relations.add(sieved);
+        assert_eq!(relations.smooths.len(), 1);
+
+        let mut sieved = Sieved::default();
+        sieved.partials.push(relation(5, 101));
+        sieved.partials.push(relation(7, 103));
+        relations.add(sieved);
+        assert_eq!(relations.smooths.len(), 2);
+        assert_eq!(relations.smooths[1].lhs, 3 * 5);
+        assert_eq!(relations.partials.len(), 2);
+        assert_eq!(relations.partials.stats.too_large, 4);
+        let progress = relations.progress(Duration::from_secs(1));
+        assert_eq!((progress.full, progress.combined), (1, 1));
+        assert_eq!(progress.target, 4 + algebra::RELATION_EXCESS);
+        assert_eq!(progress.cofactors.too_large, 4);
+        assert_eq!(progress.rate(), 2.0);
+    }
+}
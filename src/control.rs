@@ -0,0 +1,120 @@
+use std::cell::Cell;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+use crate::backend::Relations;
+use crate::error::FactorError;
+use crate::report::ProgressHook;
+
+/// Longest a coordinator waits for the workers before looking at the handle again
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Stops a run from another thread, between two polynomials
+#[derive(Clone, Debug, Default)]
+pub struct CancelHandle {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancelHandle {
+    pub fn new() -> CancelHandle {
+        CancelHandle::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+/// How a run is stopped and watched from outside: deadline, cancel handle and progress hook
+#[derive(Clone, Debug)]
+pub struct Control {
+    pub start: Instant,
+    pub deadline: Option<Instant>,
+    pub cancel: CancelHandle,
+    pub progress: Option<ProgressHook>,
+    /// When the hook was last called
+    reported: Cell<Instant>,
+}
+
+impl Default for Control {
+    fn default() -> Control {
+        Control::new(Instant::now(), None, CancelHandle::new(), None)
+    }
+}
+
+impl Control {
+    /// Control of a run started at `start`, `time_limit` is counted from `start`
+    pub fn new(
+        start: Instant,
+        time_limit: Option<Duration>,
+        cancel: CancelHandle,
+        progress: Option<ProgressHook>,
+    ) -> Control {
+        Control {
+            start,
+            deadline: time_limit.map(|limit| start + limit),
+            cancel,
+            progress,
+            reported: Cell::new(start),
+        }
+    }
+
+    /// Calls the progress hook when it is due, then gives `Cancelled` or `TimeLimit`
+    /// with the state of `relations` once the run has to stop
+    pub(crate) fn check(&self, relations: &Relations) -> Result<(), FactorError> {
+        let progress = || relations.progress(self.start.elapsed());
+        if let Some(hook) = &self.progress {
+            if self.reported.get().elapsed() >= hook.interval {
+                self.reported.set(Instant::now());
+                hook.call(&progress());
+            }
+        }
+        if self.cancel.is_cancelled() {
+            Err(FactorError::Cancelled(progress()))
+        } else if self.expired() {
+            Err(FactorError::TimeLimit(progress()))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Whether the deadline has passed
+    fn expired(&self) -> bool {
+        self.deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    /// Whether the run has to stop, for the steps that have no relations to report
+    pub(crate) fn stopped(&self) -> bool {
+        self.cancel.is_cancelled() || self.expired()
+    }
+
+    /// Waits for a message on `receiver` until the run has to stop,
+    /// the channel is only closed once every worker holding a sender is gone
+    pub(crate) fn recv<T>(
+        &self,
+        receiver: &Receiver<T>,
+        relations: &Relations,
+    ) -> Result<T, FactorError> {
+        loop {
+            self.check(relations)?;
+            let wait = match self.deadline {
+                Some(deadline) => {
+                    POLL_INTERVAL.min(deadline.saturating_duration_since(Instant::now()))
+                }
+                None => POLL_INTERVAL,
+            };
+            match receiver.recv_timeout(wait) {
+                Ok(message) => return Ok(message),
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => return Err(FactorError::WorkerFailure),
+            }
+        }
+    }
+}
@@ -0,0 +1,33 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::report::Progress;
+
+/// Why a run ended without a factor
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FactorError {
+    /// `n` is not a composite the sieve can split, or the parameters make no sense
+    InvalidInput(String),
+    /// The named parameter, or a value the sieve derives from it, does not fit in the type that stores it
+    ParameterOverflow(&'static str),
+    /// Every worker thread is gone, or one of them panicked before a factor was found
+    WorkerFailure,
+    /// The time limit passed before a factor was found
+    TimeLimit(Progress),
+    /// The run was stopped through its `CancelHandle`
+    Cancelled(Progress),
+}
+
+impl fmt::Display for FactorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FactorError::InvalidInput(reason) => write!(f, "invalid input: {}", reason),
+            FactorError::ParameterOverflow(name) => write!(f, "parameter overflow: {}", name),
+            FactorError::WorkerFailure => write!(f, "a worker thread failed"),
+            FactorError::TimeLimit(progress) => write!(f, "time limit reached, {}", progress),
+            FactorError::Cancelled(progress) => write!(f, "cancelled, {}", progress),
+        }
+    }
+}
+
+impl Error for FactorError {}
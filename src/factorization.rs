@@ -0,0 +1,103 @@
+use std::fmt;
+
+use rug::Integer;
+use rug::integer::IsPrime;
+use rug::ops::Pow;
+
+/// Rounds of Miller–Rabin a factor has to pass to be taken as prime
+pub const PRIME_REPS: u32 = 25;
+
+/// Prime factorization of a number, the distinct primes in increasing order with their exponents
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Factorization {
+    factors: Vec<(Integer, u32)>,
+}
+
+impl Factorization {
+    pub fn new() -> Factorization {
+        Factorization::default()
+    }
+
+    /// Multiplies the factorization by `prime^exponent`
+    pub fn add(&mut self, prime: Integer, exponent: u32) {
+        match self.factors.binary_search_by(|(p, _)| p.cmp(&prime)) {
+            Ok(i) => self.factors[i].1 += exponent,
+            Err(i) => self.factors.insert(i, (prime, exponent)),
+        }
+    }
+
+    pub fn factors(&self) -> &[(Integer, u32)] {
+        &self.factors
+    }
+
+    pub fn product(&self) -> Integer {
+        self.factors
+            .iter()
+            .map(|(p, e)| p.clone().pow(*e))
+            .product()
+    }
+
+    /// Whether the product is `n` and every factor is prime
+    pub fn verify(&self, n: &Integer) -> bool {
+        self.product() == *n
+            && self
+                .factors
+                .iter()
+                .all(|(p, _)| p.is_probably_prime(PRIME_REPS) != IsPrime::No)
+    }
+}
+
+impl fmt::Display for Factorization {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.factors.is_empty() {
+            return write!(f, "1");
+        }
+        for (i, (p, e)) in self.factors.iter().enumerate() {
+            if i > 0 {
+                write!(f, " * ")?;
+            }
+            match e {
+                1 => write!(f, "{}", p)?,
+                e => write!(f, "{}^{}", p, e)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Smallest prime factor of `n > 1`, by trial division up to its square root
+pub fn smallest_factor(n: u64) -> u64 {
+    if n.is_multiple_of(2) {
+        return 2;
+    }
+    let mut d = 3;
+    while d * d <= n {
+        if n.is_multiple_of(d) {
+            return d;
+        }
+        d += 2;
+    }
+    n
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_factorization() {
+        let mut factorization = Factorization::new();
+        factorization.add(Integer::from(7), 1);
+        factorization.add(Integer::from(2), 3);
+        factorization.add(Integer::from(7), 1);
+        assert_eq!(factorization.to_string(), "2^3 * 7^2");
+        assert!(factorization.verify(&Integer::from(392)));
+        assert!(!factorization.verify(&Integer::from(391)));
+
+        factorization.add(Integer::from(15), 1);
+        assert!(!factorization.verify(&Integer::from(392 * 15)));
+
+        assert_eq!(smallest_factor(99991 * 99877), 99877);
+        assert_eq!(smallest_factor(1_000_000_007), 1_000_000_007);
+    }
+}
@@ -0,0 +1,421 @@
+use std::time::{Duration, Instant};
+
+use rug::Integer;
+use rug::integer::IsPrime;
+
+use crate::backend::{self, Backend};
+use crate::control::{CancelHandle, Control};
+use crate::error::FactorError;
+use crate::factorization::{smallest_factor, Factorization, PRIME_REPS};
+use crate::memory_shared_MPQS::MemoryShared;
+use crate::message_MPQS::MessagePassing;
+use crate::params::Params;
+use crate::pm1::{pm1, Pm1Bounds};
+use crate::polynomial::PolynomialKind;
+use crate::preprocess::{perfect_power, trial_division, TRIAL_BOUND};
+use crate::report::{Progress, ProgressHook, RunReport};
+use crate::rho::rho;
+use crate::serial_MPQS::{initialize_with, Serial};
+
+/// Composite pieces below this bound are split by trial division instead of the sieve
+const SMALL_PIECE: u64 = 1 << 40;
+
+/// Iterations of Pollard's rho on a piece before it is handed to the sieve,
+/// enough for the factors of up to about nine digits
+const RHO_ITERATIONS: u64 = 1 << 15;
+
+/// How the sieving is spread over threads
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Algorithm {
+    /// Everything on the calling thread
+    Serial,
+    /// Worker threads share the polynomial seeder and the relations behind locks
+    MemoryShared,
+    /// Worker threads exchange polynomials and relations with a coordinator over channels
+    MessagePassing,
+}
+
+/// Builder for a factorization run
+#[derive(Clone, Debug)]
+pub struct Factorizer {
+    algorithm: Algorithm,
+    threads: usize,
+    params: Params,
+    time_limit: Option<Duration>,
+    cancel: CancelHandle,
+    progress: Option<ProgressHook>,
+    pm1: Option<Pm1Bounds>,
+}
+
+/// Outcome of `Factorizer::factor`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FactorResult {
+    pub n: Integer,
+    /// Nontrivial factor of `n`
+    pub factor: Integer,
+    pub algorithm: Algorithm,
+    /// Knuth–Schroeppel multiplier of `n`
+    pub multiplier: u64,
+    pub fb_size: usize,
+    pub elapsed: Duration,
+    pub report: RunReport,
+}
+
+impl FactorResult {
+    /// `n` divided by `factor`
+    pub fn cofactor(&self) -> Integer {
+        Integer::from(&self.n / &self.factor)
+    }
+}
+
+impl Default for Factorizer {
+    fn default() -> Factorizer {
+        Factorizer {
+            algorithm: Algorithm::Serial,
+            threads: num_cpus::get(),
+            params: Params::default(),
+            time_limit: None,
+            cancel: CancelHandle::new(),
+            progress: None,
+            pm1: Some(Pm1Bounds::default()),
+        }
+    }
+}
+
+impl Factorizer {
+    /// Serial sieve with the parameter table and no time limit
+    pub fn new() -> Factorizer {
+        Factorizer::default()
+    }
+
+    pub fn algorithm(mut self, algorithm: Algorithm) -> Factorizer {
+        self.algorithm = algorithm;
+        self
+    }
+
+    /// Worker threads of the parallel algorithms, all the cores by default
+    pub fn threads(mut self, threads: usize) -> Factorizer {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Overrides of the parameter table
+    pub fn params(mut self, params: Params) -> Factorizer {
+        self.params = params;
+        self
+    }
+
+    /// Gives up after `time_limit`, counted from the start of `factor`
+    pub fn time_limit(mut self, time_limit: Duration) -> Factorizer {
+        self.time_limit = Some(time_limit);
+        self
+    }
+
+    /// Handle that stops the run from another thread, which then gives up with `Cancelled`
+    pub fn cancel_handle(mut self, cancel: CancelHandle) -> Factorizer {
+        self.cancel = cancel;
+        self
+    }
+
+    /// Calls `callback` with the progress of the run at most once every `interval`
+    pub fn on_progress<F>(mut self, interval: Duration, callback: F) -> Factorizer
+    where
+        F: Fn(&Progress) + Send + Sync + 'static,
+    {
+        self.progress = Some(ProgressHook::new(interval, callback));
+        self
+    }
+
+    /// Bounds of Pollard's p − 1 tried by `factorize` on the pieces left by rho, `None` skips it
+    pub fn pm1(mut self, bounds: Option<Pm1Bounds>) -> Factorizer {
+        self.pm1 = bounds;
+        self
+    }
+
+    /// Seed of the random choice of the polynomials, for reproducible runs
+    pub fn seed(mut self, seed: u64) -> Factorizer {
+        self.params.seed = seed;
+        self
+    }
+
+    /// How the polynomials are generated, SIQS by default
+    pub fn polynomial(mut self, kind: PolynomialKind) -> Factorizer {
+        self.params.polynomial = kind;
+        self
+    }
+
+    /// Implementation of the chosen algorithm
+    fn backend(&self) -> Box<dyn Backend> {
+        match self.algorithm {
+            Algorithm::Serial => Box::new(Serial),
+            Algorithm::MemoryShared => Box::new(MemoryShared {
+                threads: self.threads,
+            }),
+            Algorithm::MessagePassing => Box::new(MessagePassing {
+                threads: self.threads,
+            }),
+        }
+    }
+
+    /// Limits and hooks of a run starting now
+    fn control(&self) -> Control {
+        Control::new(
+            Instant::now(),
+            self.time_limit,
+            self.cancel.clone(),
+            self.progress.clone(),
+        )
+    }
+
+    /// Looks for a nontrivial factor of the composite `n`
+    pub fn factor(&self, n: &Integer) -> Result<FactorResult, FactorError> {
+        self.split(n, &self.control())
+    }
+
+    /// Prime factorization of `n`. The small primes are removed by trial division
+    /// and perfect powers are replaced by their root. The other composite pieces are split
+    /// by Pollard's rho when they have a small factor, by Pollard's p − 1 when they have
+    /// a factor `p` with `p − 1` smooth and by the sieve otherwise, until they are prime.
+    /// The time limit and the cancel handle apply to the whole factorization
+    pub fn factorize(&self, n: &Integer) -> Result<Factorization, FactorError> {
+        if *n < 1 {
+            return Err(FactorError::InvalidInput(format!("{} is not positive", n)));
+        }
+        let control = self.control();
+        let (mut factorization, rest) = trial_division(n, TRIAL_BOUND);
+        let mut pieces = vec![(rest, 1)];
+
+        while let Some((piece, exponent)) = pieces.pop() {
+            if piece == 1 {
+                continue;
+            }
+            if piece.is_probably_prime(PRIME_REPS) != IsPrime::No {
+                factorization.add(piece, exponent);
+                continue;
+            }
+            if let Some((root, k)) = perfect_power(&piece) {
+                pieces.push((root, k * exponent));
+                continue;
+            }
+            let factor = match piece.to_u64() {
+                Some(small) if small < SMALL_PIECE => Integer::from(smallest_factor(small)),
+                _ => match rho(&piece, RHO_ITERATIONS, || control.stopped()).or_else(|| {
+                    self.pm1
+                        .and_then(|bounds| pm1(&piece, &bounds, || control.stopped()))
+                }) {
+                    Some(factor) => factor,
+                    None => self.split(&piece, &control)?.factor,
+                },
+            };
+            let cofactor = Integer::from(&piece / &factor);
+            pieces.push((factor, exponent));
+            pieces.push((cofactor, exponent));
+        }
+        Ok(factorization)
+    }
+
+    /// Runs the sieve on `n` under `control`
+    fn split(&self, n: &Integer, control: &Control) -> Result<FactorResult, FactorError> {
+        let start = Instant::now();
+        let init = initialize_with(n, &self.params)?;
+        let init_time = start.elapsed();
+        let multiplier = Integer::from(&init.context.kn / n).to_u64().unwrap();
+        let fb_size = init.context.factorbase.len();
+
+        let (factor, mut report) = backend::run(&*self.backend(), n, init, control)?;
+        report.timings.init = init_time;
+        Ok(FactorResult {
+            n: n.clone(),
+            factor,
+            algorithm: self.algorithm,
+            multiplier,
+            fb_size,
+            elapsed: start.elapsed(),
+            report,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use rug::ops::Pow;
+
+    use super::*;
+    use crate::algebra;
+
+    const ALGORITHMS: [Algorithm; 3] = [
+        Algorithm::Serial,
+        Algorithm::MemoryShared,
+        Algorithm::MessagePassing,
+    ];
+
+    #[test]
+    fn test_factorizer() {
+        let n = "9986801107".parse::<Integer>().unwrap();
+        for algorithm in ALGORITHMS.iter() {
+            let calls = Arc::new(AtomicUsize::new(0));
+            let counter = Arc::clone(&calls);
+            let result = Factorizer::new()
+                .algorithm(*algorithm)
+                .threads(2)
+                .seed(7)
+                .on_progress(Duration::ZERO, move |_| {
+                    counter.fetch_add(1, Ordering::Relaxed);
+                })
+                .factor(&n)
+                .unwrap();
+            assert_eq!(result.factor.clone() * result.cofactor(), n);
+            assert!(calls.load(Ordering::Relaxed) > 0);
+            let report = result.report;
+            assert_eq!(
+                report.progress.target,
+                result.fb_size + algebra::RELATION_EXCESS
+            );
+            assert!(report.progress.relations() >= report.progress.target);
+            assert!(report.progress.polynomials > 0);
+            assert!(report.timings.sieve > Duration::ZERO);
+            let cofactors = report.progress.cofactors;
+            assert!(cofactors.single > 0);
+            assert!(
+                cofactors.single + cofactors.double
+                    >= report.progress.partials + report.progress.combined
+            );
+        }
+        let result = Factorizer::new()
+            .polynomial(PolynomialKind::Mpqs)
+            .factor(&n);
+        assert!(result.is_ok());
+
+        let n = "523022617466601111760007224100074291200000001"
+            .parse::<Integer>()
+            .unwrap();
+        for algorithm in ALGORITHMS.iter() {
+            // Returns only once the workers have stopped
+            let result = Factorizer::new()
+                .algorithm(*algorithm)
+                .threads(2)
+                .time_limit(Duration::from_millis(1))
+                .factor(&n);
+            assert!(matches!(result, Err(FactorError::TimeLimit(_))));
+        }
+    }
+
+    #[test]
+    fn test_factorize() {
+        let n = Integer::from(2).pow(3)
+            * 9
+            * Integer::from(99991).pow(2)
+            * 99877
+            * Integer::from(1_000_000_007)
+            * 1_000_000_009;
+        let factorization = Factorizer::new().factorize(&n).unwrap();
+        assert!(factorization.verify(&n));
+        assert_eq!(
+            factorization.to_string(),
+            "2^3 * 3^2 * 99877 * 99991^2 * 1000000007 * 1000000009"
+        );
+
+        let big = Integer::from(10).pow(35).next_prime();
+        let factorization = Factorizer::new()
+            .factorize(&(big.clone() * 1_000_003))
+            .unwrap();
+        assert_eq!(factorization.factors()[0], (Integer::from(1_000_003), 1));
+        assert_eq!(factorization.factors()[1], (big.clone(), 1));
+
+        // Out of reach of rho, p - 1 is the product of the primes up to 47 and a small k
+        let primorial = (2..=47_u32).fold(Integer::from(1), |acc, q| {
+            if smallest_factor(q as u64) == q as u64 {
+                acc * q
+            } else {
+                acc
+            }
+        });
+        let p = (1_u32..)
+            .map(|k| primorial.clone() * k + 1)
+            .find(|p: &Integer| p.is_probably_prime(PRIME_REPS) != IsPrime::No)
+            .unwrap();
+        let factorization = Factorizer::new().factorize(&(p.clone() * &big)).unwrap();
+        assert_eq!(factorization.factors()[0], (p, 1));
+        assert_eq!(factorization.factors()[1], (big, 1));
+
+        let n = Integer::from(1_000_000_007_u64 * 1_000_000_009).pow(3) * 65521;
+        let factorization = Factorizer::new().factorize(&n).unwrap();
+        assert_eq!(
+            factorization.to_string(),
+            "65521 * 1000000007^3 * 1000000009^3"
+        );
+
+        assert_eq!(
+            Factorizer::new().factorize(&Integer::from(1)),
+            Ok(Factorization::new())
+        );
+        let result = Factorizer::new().factorize(&Integer::from(0));
+        assert!(matches!(result, Err(FactorError::InvalidInput(_))));
+    }
+
+    #[test]
+    fn test_cancel() {
+        let n = "676292275716558246502605230897191366469551764092181362779759"
+            .parse::<Integer>()
+            .unwrap();
+        for algorithm in ALGORITHMS.iter() {
+            let cancel = CancelHandle::new();
+            let factorizer = Factorizer::new()
+                .algorithm(*algorithm)
+                .threads(2)
+                .cancel_handle(cancel.clone());
+            let result = std::thread::scope(|scope| {
+                let run = scope.spawn(|| factorizer.factor(&n));
+                std::thread::sleep(Duration::from_millis(200));
+                cancel.cancel();
+                run.join().unwrap()
+            });
+            match result {
+                Err(FactorError::Cancelled(progress)) => {
+                    assert!(progress.elapsed >= Duration::from_millis(200));
+                    let cofactors = progress.cofactors;
+                    assert!(cofactors.single + cofactors.double >= progress.partials);
+                }
+                result => panic!("{:?}", result),
+            }
+        }
+
+        let cancel = CancelHandle::new();
+        cancel.cancel();
+        let result = Factorizer::new().cancel_handle(cancel).factorize(&n);
+        assert!(matches!(result, Err(FactorError::Cancelled(_))));
+    }
+
+    #[test]
+    fn test_errors() {
+        let factor = |n: &str, params: Params| {
+            Factorizer::new()
+                .params(params)
+                .factor(&n.parse::<Integer>().unwrap())
+        };
+        let invalid = |result| matches!(result, Err(FactorError::InvalidInput(_)));
+        assert!(invalid(factor("1", Params::default())));
+        assert!(invalid(factor("1000000007", Params::default())));
+        let params = Params {
+            fb_size: Some(1),
+            ..Params::default()
+        };
+        assert!(invalid(factor("9986801107", params)));
+
+        let params = Params {
+            xmax: Some(i64::MAX),
+            ..Params::default()
+        };
+        let result = factor("9986801107", params);
+        assert_eq!(result, Err(FactorError::ParameterOverflow("xmax")));
+        let params = Params {
+            thresh_offset: Some(-1000.0),
+            ..Params::default()
+        };
+        let result = factor("9986801107", params);
+        assert_eq!(result, Err(FactorError::ParameterOverflow("thresh_offset")));
+    }
+}
@@ -0,0 +1,153 @@
+use std::cmp::min;
+
+use rug::Integer;
+
+use crate::large_primes::{split_cofactor, CofactorStats, LargePrimeBounds};
+use crate::polynomial::{Family, FamilySeed, Polynomial};
+use crate::relation::Relation;
+use crate::sieve::{
+    candidates, divide_out, resieve, sieve_block, Buckets, Patterns, PrimePower, SieveRoots,
+    BLOCK_SIZE,
+};
+
+/// Everything the sieve needs besides the polynomials, shared by all the threads of a run
+pub struct SieveContext {
+    /// The number actually sieved, `n` times the Knuth–Schroeppel multiplier
+    pub kn: Integer,
+    pub factorbase: Vec<u64>,
+    /// Square roots of `kn` modulo every prime of the factor base
+    pub tsqrt: Vec<u32>,
+    /// Powers of the small factor base primes, sieved besides the primes
+    pub powers: Vec<PrimePower>,
+    pub xmax: i64,
+    /// Rounded base 2 logarithms of the factor base primes, the exponent of 2 for the prime 2
+    pub tlog: Vec<u8>,
+    /// Positions whose sum of logarithms is above `thresh` are trial divided
+    pub thresh: u8,
+    pub bounds: LargePrimeBounds,
+}
+
+/// Relations found by the sieve, before the partials are combined
+#[derive(Default)]
+pub struct Sieved {
+    pub smooths: Vec<Relation>,
+    /// Relations with one or two large primes
+    pub partials: Vec<Relation>,
+    pub stats: CofactorStats,
+    /// Polynomials sieved
+    pub polynomials: usize,
+}
+
+/// The sieve of one thread, with its own block
+pub struct SieveKernel<'a> {
+    context: &'a SieveContext,
+    block: Vec<u8>,
+}
+
+impl<'a> SieveKernel<'a> {
+    pub fn new(context: &'a SieveContext) -> SieveKernel<'a> {
+        SieveKernel {
+            context,
+            block: vec![0_u8; BLOCK_SIZE],
+        }
+    }
+
+    /// Sieves every polynomial of the family of `seed`
+    pub fn family(&mut self, seed: FamilySeed, sieved: &mut Sieved) {
+        let context = self.context;
+        for polynomial in Family::new(&context.kn, &context.factorbase, &context.tsqrt, seed) {
+            self.polynomial(&polynomial, sieved);
+        }
+    }
+
+    /// Sieves `polynomial` over `[-xmax, xmax]` and adds the relations it gives to `sieved`
+    pub fn polynomial(&mut self, polynomial: &Polynomial, sieved: &mut Sieved) {
+        let SieveContext {
+            factorbase,
+            powers,
+            xmax,
+            tlog,
+            thresh,
+            bounds,
+            ..
+        } = self.context;
+        let (xmax, thresh) = (*xmax, *thresh);
+        sieved.polynomials += 1;
+
+        let mut roots = SieveRoots::new(polynomial, factorbase, tlog, powers, xmax);
+        let patterns = Patterns::new(&roots, factorbase, tlog);
+        let buckets = Buckets::new(&roots, factorbase, 2 * xmax as usize + 1);
+
+        for (k, low) in (-xmax..xmax + 1).step_by(BLOCK_SIZE).enumerate() {
+            let size = min(BLOCK_SIZE as i64, xmax + 1 - low) as usize;
+            let block = &mut self.block[..size];
+            sieve_block(
+                block,
+                k * BLOCK_SIZE,
+                factorbase,
+                tlog,
+                &mut roots,
+                &patterns,
+                buckets.block(k),
+            );
+
+            let hits: Vec<usize> = candidates(block, thresh).collect();
+            let divisors = resieve(block, thresh, &hits, factorbase, &roots, buckets.block(k));
+            for (i, divisors) in hits.iter().zip(divisors) {
+                let x = *i as i64 + low;
+                let tofact = polynomial.value(x);
+                let (nf, factors) = divide_out(&tofact, &divisors, factorbase);
+                if nf == 1 {
+                    let large_primes = (Integer::from(1), Integer::from(1));
+                    let relation = polynomial.relation(x, &tofact, factors, large_primes);
+                    sieved.smooths.push(relation);
+                } else {
+                    match split_cofactor(nf, bounds) {
+                        Ok(large_primes) => {
+                            let relation = polynomial.relation(x, &tofact, factors, large_primes);
+                            sieved.partials.push(relation);
+                        }
+                        Err(rejection) => sieved.stats.reject(rejection),
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rug::ops::Pow;
+    use rug::Integer;
+
+    use crate::serial_MPQS::{initialize_qs, InitResult};
+
+    use super::*;
+
+    #[test]
+    fn test_relations() {
+        let n = "523022617466601111760007224100074291200000001"
+            .parse::<Integer>()
+            .unwrap();
+        let InitResult {
+            context,
+            mut seeder,
+        } = initialize_qs(&n).unwrap();
+        let seed = seeder.next_seed(&context.kn, &context.factorbase);
+
+        let mut sieved = Sieved::default();
+        SieveKernel::new(&context).family(seed, &mut sieved);
+        assert!(!sieved.smooths.is_empty());
+        for relation in sieved.smooths.iter().chain(&sieved.partials) {
+            let mut rhs = Integer::from(if relation.negative { -1 } else { 1 });
+            for (i, e) in relation.factors.iter() {
+                rhs *= Integer::from(context.factorbase[*i]).pow(*e);
+            }
+            rhs *= &relation.large_primes.0;
+            rhs *= &relation.large_primes.1;
+            rhs *= relation.ra.clone().square();
+            let lhs = relation.lhs.clone().square();
+            assert!((lhs - rhs).is_divisible(&context.kn));
+        }
+    }
+}
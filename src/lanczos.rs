@@ -0,0 +1,321 @@
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+
+/// Montgomery's Block Lanczos over GF(2), working on blocks of 64 vectors at a time.
+/// `columns[j]` lists the rows where column `j` has a 1.
+/// Returns sets of columns that sum to zero, or `None` if the iteration broke down.
+pub fn find_dependencies(columns: &[Vec<u32>], nrows: usize) -> Option<Vec<Vec<usize>>> {
+    let (matrix, kept) = SparseMatrix::without_singletons(columns, nrows);
+    let n = matrix.columns.len();
+    if n < 64 {
+        return None;
+    }
+
+    let mut rng = StdRng::seed_from_u64(n as u64);
+    let y: Vec<u64> = (0..n).map(|_| rng.gen()).collect();
+    let v0 = matrix.mul_symmetric(&y);
+
+    let mut x = vec![0_u64; n];
+    let mut v = v0.clone();
+    let mut v_prev = vec![0_u64; n];
+    let mut v_prev2 = vec![0_u64; n];
+    let mut winv_prev = [0_u64; 64];
+    let mut winv_prev2 = [0_u64; 64];
+    let mut vav_prev = [0_u64; 64];
+    let mut vaav_prev = [0_u64; 64];
+    let mut mask_prev = u64::MAX;
+
+    let max_iterations = n / 60 + 100;
+    let mut iterations = 0;
+    loop {
+        let av = matrix.mul_symmetric(&v);
+        let vav = transpose_mul(&v, &av);
+        if vav.iter().all(|r| *r == 0) {
+            break;
+        }
+        let vaav = transpose_mul(&av, &av);
+        let (winv, mask) = find_winv(&vav, mask_prev)?;
+
+        let coefficients = mul_64(&winv, &transpose_mul(&v, &v0));
+        xor_into(&mut x, &mul_block(&v, &coefficients));
+
+        let d = add_identity(&mul_64(&winv, &add(&mask_columns(&vaav, mask), &vav)));
+        let e = mul_64(&winv_prev, &mask_columns(&vav, mask));
+        let f = mul_64(
+            &mul_64(&winv_prev2, &add_identity(&mul_64(&vav_prev, &winv_prev))),
+            &mask_columns(&add(&mask_columns(&vaav_prev, mask_prev), &vav_prev), mask),
+        );
+
+        let mut next: Vec<u64> = av.iter().map(|r| r & mask).collect();
+        xor_into(&mut next, &mul_block(&v, &d));
+        xor_into(&mut next, &mul_block(&v_prev, &e));
+        xor_into(&mut next, &mul_block(&v_prev2, &f));
+
+        v_prev2 = std::mem::replace(&mut v_prev, std::mem::replace(&mut v, next));
+        winv_prev2 = std::mem::replace(&mut winv_prev, winv);
+        vav_prev = vav;
+        vaav_prev = vaav;
+        mask_prev = mask;
+
+        iterations += 1;
+        if iterations > max_iterations {
+            return None;
+        }
+    }
+
+    xor_into(&mut x, &y);
+    let dependencies = combine(&matrix, &x, &v)
+        .into_iter()
+        .map(|dependency| dependency.into_iter().map(|j| kept[j]).collect())
+        .collect();
+    Some(dependencies)
+}
+
+struct SparseMatrix {
+    columns: Vec<Vec<u32>>,
+    nrows: usize,
+}
+
+impl SparseMatrix {
+    /// Columns touching a row that appears only once can't be part of any dependency
+    fn without_singletons(columns: &[Vec<u32>], nrows: usize) -> (SparseMatrix, Vec<usize>) {
+        let mut kept: Vec<usize> = (0..columns.len()).collect();
+        loop {
+            let mut weight = vec![0_u32; nrows];
+            for j in kept.iter() {
+                columns[*j].iter().for_each(|r| weight[*r as usize] += 1);
+            }
+            let before = kept.len();
+            kept.retain(|j| columns[*j].iter().all(|r| weight[*r as usize] != 1));
+            if kept.len() == before {
+                let mut renumber = vec![u32::MAX; nrows];
+                let mut nrows = 0;
+                for (r, w) in weight.iter().enumerate() {
+                    if *w > 0 {
+                        renumber[r] = nrows;
+                        nrows += 1;
+                    }
+                }
+                let columns = kept
+                    .iter()
+                    .map(|j| columns[*j].iter().map(|r| renumber[*r as usize]).collect())
+                    .collect();
+                let matrix = SparseMatrix {
+                    columns,
+                    nrows: nrows as usize,
+                };
+                return (matrix, kept);
+            }
+        }
+    }
+
+    /// `B v`
+    fn mul(&self, v: &[u64]) -> Vec<u64> {
+        let mut out = vec![0_u64; self.nrows];
+        for (rows, x) in self.columns.iter().zip(v) {
+            if *x != 0 {
+                rows.iter().for_each(|r| out[*r as usize] ^= x);
+            }
+        }
+        out
+    }
+
+    /// `B^T B v`
+    fn mul_symmetric(&self, v: &[u64]) -> Vec<u64> {
+        let bv = self.mul(v);
+        self.columns
+            .iter()
+            .map(|rows| rows.iter().fold(0, |acc, r| acc ^ bv[*r as usize]))
+            .collect()
+    }
+}
+
+/// Chooses the columns `S_i` of `V_i` to keep and computes
+/// `Winv = S_i (S_i^T vav S_i)^-1 S_i^T`, following section 8 of Montgomery's paper
+fn find_winv(vav: &[u64; 64], mask_prev: u64) -> Option<([u64; 64], u64)> {
+    let mut m: Vec<(u64, u64)> = (0..64).map(|r| (vav[r], 1_u64 << r)).collect();
+    let order: Vec<usize> = (0..64)
+        .filter(|c| mask_prev & (1 << c) == 0)
+        .chain((0..64).filter(|c| mask_prev & (1 << c) != 0))
+        .collect();
+    let mut mask = 0_u64;
+
+    for k in 0..64 {
+        let ck = order[k];
+        let bit = 1_u64 << ck;
+        if let Some(j) = (k..64).find(|j| m[order[*j]].0 & bit != 0) {
+            m.swap(ck, order[j]);
+            mask |= bit;
+            let pivot = m[ck];
+            for (r, row) in m.iter_mut().enumerate() {
+                if r != ck && row.0 & bit != 0 {
+                    row.0 ^= pivot.0;
+                    row.1 ^= pivot.1;
+                }
+            }
+        } else {
+            let j = (k..64).find(|j| m[order[*j]].1 & bit != 0)?;
+            m.swap(ck, order[j]);
+            let pivot = m[ck];
+            for (r, row) in m.iter_mut().enumerate() {
+                if r != ck && row.1 & bit != 0 {
+                    row.0 ^= pivot.0;
+                    row.1 ^= pivot.1;
+                }
+            }
+            m[ck] = (0, 0);
+        }
+    }
+
+    let mut winv = [0_u64; 64];
+    for (r, row) in m.iter().enumerate() {
+        winv[r] = row.1;
+    }
+    Some((winv, mask))
+}
+
+/// Finds the combinations of the columns of `x` and `v` that are in the kernel of `B`
+fn combine(matrix: &SparseMatrix, x: &[u64], v: &[u64]) -> Vec<Vec<usize>> {
+    let bx = matrix.mul(x);
+    let bv = matrix.mul(v);
+    let words = matrix.nrows.div_ceil(64);
+
+    let mut pivots: Vec<(usize, Vec<u64>, u128)> = Vec::new();
+    let mut kernel: Vec<u128> = Vec::new();
+    for k in 0..128 {
+        let (block, bit) = if k < 64 { (&bx, k) } else { (&bv, k - 64) };
+        let mut row = vec![0_u64; words];
+        for (r, value) in block.iter().enumerate() {
+            if value >> bit & 1 == 1 {
+                row[r / 64] |= 1 << (r % 64);
+            }
+        }
+        let mut combination = 1_u128 << k;
+        for (lead, pivot, pivot_combination) in pivots.iter() {
+            if row[lead / 64] >> (lead % 64) & 1 == 1 {
+                row.iter_mut().zip(pivot).for_each(|(a, b)| *a ^= b);
+                combination ^= pivot_combination;
+            }
+        }
+        match row.iter().position(|w| *w != 0) {
+            Some(w) => pivots.push((w * 64 + row[w].trailing_zeros() as usize, row, combination)),
+            None => kernel.push(combination),
+        }
+    }
+
+    let mut dependencies: Vec<Vec<usize>> = kernel
+        .into_iter()
+        .map(|combination| {
+            let (low, high) = (combination as u64, (combination >> 64) as u64);
+            (0..x.len())
+                .filter(|j| ((x[*j] & low) ^ (v[*j] & high)).count_ones() & 1 == 1)
+                .collect::<Vec<usize>>()
+        })
+        .filter(|dependency| !dependency.is_empty())
+        .collect();
+    dependencies.sort();
+    dependencies.dedup();
+    dependencies
+}
+
+/// `V^T W`, a 64x64 matrix
+fn transpose_mul(v: &[u64], w: &[u64]) -> [u64; 64] {
+    let mut out = [0_u64; 64];
+    for (x, y) in v.iter().zip(w) {
+        let mut x = *x;
+        while x != 0 {
+            out[x.trailing_zeros() as usize] ^= y;
+            x &= x - 1;
+        }
+    }
+    out
+}
+
+/// `V M`, where `M` is a 64x64 matrix
+fn mul_block(v: &[u64], m: &[u64; 64]) -> Vec<u64> {
+    let mut tables = vec![[0_u64; 256]; 8];
+    for (byte, table) in tables.iter_mut().enumerate() {
+        for i in 1..256 {
+            let low = i & (i - 1);
+            table[i] = table[low] ^ m[byte * 8 + (i ^ low).trailing_zeros() as usize];
+        }
+    }
+    v.iter()
+        .map(|x| {
+            tables.iter().enumerate().fold(0, |acc, (byte, table)| {
+                acc ^ table[(x >> (byte * 8)) as usize & 0xff]
+            })
+        })
+        .collect()
+}
+
+fn mul_64(a: &[u64; 64], b: &[u64; 64]) -> [u64; 64] {
+    let mut out = [0_u64; 64];
+    for (o, row) in out.iter_mut().zip(a) {
+        let mut row = *row;
+        while row != 0 {
+            *o ^= b[row.trailing_zeros() as usize];
+            row &= row - 1;
+        }
+    }
+    out
+}
+
+fn add(a: &[u64; 64], b: &[u64; 64]) -> [u64; 64] {
+    let mut out = *a;
+    out.iter_mut().zip(b).for_each(|(o, b)| *o ^= b);
+    out
+}
+
+fn add_identity(a: &[u64; 64]) -> [u64; 64] {
+    let mut out = *a;
+    out.iter_mut().enumerate().for_each(|(r, o)| *o ^= 1 << r);
+    out
+}
+
+fn mask_columns(a: &[u64; 64], mask: u64) -> [u64; 64] {
+    let mut out = *a;
+    out.iter_mut().for_each(|o| *o &= mask);
+    out
+}
+
+fn xor_into(a: &mut [u64], b: &[u64]) {
+    a.iter_mut().zip(b).for_each(|(a, b)| *a ^= b);
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+
+    #[test]
+    fn test_lanczos() {
+        let (nrows, ncols) = (1000, 1100);
+        let mut rng = StdRng::seed_from_u64(42);
+        let columns: Vec<Vec<u32>> = (0..ncols)
+            .map(|_| {
+                let mut rows: Vec<u32> = (0..rng.gen_range(3, 15))
+                    .map(|_| {
+                        let r: f64 = rng.gen();
+                        (r * r * nrows as f64) as u32
+                    })
+                    .collect();
+                rows.sort();
+                rows.dedup();
+                rows
+            })
+            .collect();
+
+        let dependencies = find_dependencies(&columns, nrows).unwrap();
+        assert!(dependencies.len() > 10);
+        for dependency in dependencies {
+            let mut parity = vec![false; nrows];
+            for j in dependency {
+                columns[j].iter().for_each(|r| parity[*r as usize] ^= true);
+            }
+            assert!(parity.iter().all(|p| !p));
+        }
+    }
+}
@@ -0,0 +1,309 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+
+use rug::Integer;
+
+use crate::rabin_miller::is_rabin_miller_prime;
+use crate::relation::Relation;
+use crate::rho::rho;
+
+/// Iterations of Pollard's rho on a cofactor, whose primes are below the large prime bound
+const RHO_ITERATIONS: u64 = 1 << 15;
+
+/// Partial relations whose cofactors are one or two large primes.
+/// Every relation is an edge between its large primes (the vertex `1` stands for a missing prime),
+/// so a cycle in the graph multiplies to a relation where every large prime appears squared.
+pub struct LargePrimeGraph {
+    vertices: HashMap<Integer, usize>,
+    values: Vec<Integer>,
+    parent: Vec<usize>,
+    adjacency: Vec<Vec<(usize, usize)>>,
+    edges: Vec<Relation>,
+    pub stats: CofactorStats,
+}
+
+impl Default for LargePrimeGraph {
+    fn default() -> Self {
+        LargePrimeGraph::new()
+    }
+}
+
+impl LargePrimeGraph {
+    pub fn new() -> LargePrimeGraph {
+        let mut graph = LargePrimeGraph {
+            vertices: HashMap::new(),
+            values: Vec::new(),
+            parent: Vec::new(),
+            adjacency: Vec::new(),
+            edges: Vec::new(),
+            stats: CofactorStats::default(),
+        };
+        graph.vertex(Integer::from(1));
+        graph
+    }
+
+    /// Number of partial relations kept in the graph
+    pub fn len(&self) -> usize {
+        self.edges.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.edges.is_empty()
+    }
+
+    /// Adds a partial relation.
+    /// Returns a full relation if the new edge closes a cycle.
+    pub fn add(&mut self, relation: Relation) -> Option<Relation> {
+        let (p, q) = relation.large_primes.clone();
+        if p == 1 {
+            self.stats.single += 1;
+        } else {
+            self.stats.double += 1;
+        }
+        let u = self.vertex(p);
+        let v = self.vertex(q);
+        let (root_u, root_v) = (self.find(u), self.find(v));
+
+        if root_u == root_v {
+            let (path_vertices, path_edges) = self.path(u, v);
+            let mut relation = relation;
+            for e in path_edges {
+                relation.combine(&self.edges[e]);
+            }
+            for vertex in path_vertices {
+                relation.ra *= &self.values[vertex];
+            }
+            relation.large_primes = (Integer::from(1), Integer::from(1));
+            Some(relation)
+        } else {
+            self.parent[root_u] = root_v;
+            let e = self.edges.len();
+            self.edges.push(relation);
+            self.adjacency[u].push((v, e));
+            self.adjacency[v].push((u, e));
+            None
+        }
+    }
+
+    fn vertex(&mut self, value: Integer) -> usize {
+        if let Some(index) = self.vertices.get(&value) {
+            return *index;
+        }
+        let index = self.values.len();
+        self.vertices.insert(value.clone(), index);
+        self.values.push(value);
+        self.parent.push(index);
+        self.adjacency.push(Vec::new());
+        index
+    }
+
+    fn find(&mut self, mut x: usize) -> usize {
+        while self.parent[x] != x {
+            self.parent[x] = self.parent[self.parent[x]];
+            x = self.parent[x];
+        }
+        x
+    }
+
+    /// Vertices and edges on the path from `u` to `v` in the spanning forest
+    fn path(&self, u: usize, v: usize) -> (Vec<usize>, Vec<usize>) {
+        let mut previous: HashMap<usize, (usize, usize)> = HashMap::new();
+        let mut queue = VecDeque::new();
+        queue.push_back(u);
+        previous.insert(u, (u, usize::MAX));
+        while let Some(x) = queue.pop_front() {
+            if x == v {
+                break;
+            }
+            for (y, e) in self.adjacency[x].iter() {
+                if !previous.contains_key(y) {
+                    previous.insert(*y, (x, *e));
+                    queue.push_back(*y);
+                }
+            }
+        }
+
+        let mut vertices = vec![v];
+        let mut edges = Vec::new();
+        let mut x = v;
+        while x != u {
+            let (y, e) = previous[&x];
+            edges.push(e);
+            vertices.push(y);
+            x = y;
+        }
+        (vertices, edges)
+    }
+}
+
+/// Which cofactors left after trial division are kept as partial relations
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LargePrimeBounds {
+    /// Largest large prime accepted
+    pub prime: u64,
+    /// Largest cofactor split into two large primes, 0 disables the double large prime variation
+    pub cofactor: u64,
+}
+
+impl LargePrimeBounds {
+    /// Bounds `multiplier` times the largest prime of the factor base and that to the power of `exponent`,
+    /// an exponent of 0 disables the double large prime variation
+    pub fn new(largest_prime: u64, multiplier: u64, exponent: f64) -> LargePrimeBounds {
+        let prime = largest_prime * multiplier;
+        let cofactor = if exponent > 0_f64 {
+            (prime as f64).powf(exponent) as u64
+        } else {
+            0
+        };
+        LargePrimeBounds { prime, cofactor }
+    }
+}
+
+/// Why a cofactor was not kept
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rejection {
+    /// The cofactor, or one of its two primes, is above the bounds
+    TooLarge,
+    /// The cofactor is not a product of at most two primes
+    Composite,
+    /// Pollard rho could not split the cofactor
+    Unsplit,
+}
+
+/// Outcome of the cofactors the sieve left over, kept or rejected
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CofactorStats {
+    pub single: usize,
+    pub double: usize,
+    pub too_large: usize,
+    pub composite: usize,
+    pub unsplit: usize,
+}
+
+impl CofactorStats {
+    pub fn reject(&mut self, rejection: Rejection) {
+        match rejection {
+            Rejection::TooLarge => self.too_large += 1,
+            Rejection::Composite => self.composite += 1,
+            Rejection::Unsplit => self.unsplit += 1,
+        }
+    }
+
+    pub fn merge(&mut self, other: &CofactorStats) {
+        self.single += other.single;
+        self.double += other.double;
+        self.too_large += other.too_large;
+        self.composite += other.composite;
+        self.unsplit += other.unsplit;
+    }
+}
+
+impl fmt::Display for CofactorStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} single and {} double large prime partials, rejected {} too large, {} composite, {} not split",
+            self.single, self.double, self.too_large, self.composite, self.unsplit
+        )
+    }
+}
+
+/// Splits the cofactor left after trial division into (at most) two large primes within `bounds`
+pub fn split_cofactor(
+    nf: Integer,
+    bounds: &LargePrimeBounds,
+) -> Result<(Integer, Integer), Rejection> {
+    let m = match nf.to_u64() {
+        Some(m) if m <= bounds.prime.max(bounds.cofactor) => m,
+        _ => return Err(Rejection::TooLarge),
+    };
+    if is_rabin_miller_prime(&nf) {
+        return if m <= bounds.prime {
+            Ok((Integer::from(1), nf))
+        } else {
+            Err(Rejection::TooLarge)
+        };
+    }
+    if m <= bounds.prime {
+        return Err(Rejection::Composite);
+    }
+
+    let d = rho(&m, RHO_ITERATIONS, || false).ok_or(Rejection::Unsplit)?;
+    let (p, q) = (Integer::from(d), Integer::from(m / d));
+    if !is_rabin_miller_prime(&p) || !is_rabin_miller_prime(&q) {
+        Err(Rejection::Composite)
+    } else if d > bounds.prime || m / d > bounds.prime {
+        Err(Rejection::TooLarge)
+    } else {
+        Ok((p, q))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rug::Integer;
+
+    use super::*;
+
+    #[test]
+    fn test_cycle() {
+        let mut graph = LargePrimeGraph::new();
+        let relation = |i: usize, p: u64, q: u64| Relation {
+            lhs: Integer::from(i),
+            negative: false,
+            factors: vec![(i, 1)],
+            large_primes: (Integer::from(p), Integer::from(q)),
+            ra: Integer::from(1),
+            origins: Vec::new(),
+        };
+
+        assert!(graph.add(relation(2, 1, 101)).is_none());
+        assert!(graph.add(relation(3, 101, 103)).is_none());
+        assert!(graph.add(relation(5, 107, 109)).is_none());
+        let relation = graph.add(relation(7, 1, 103)).unwrap();
+        assert_eq!(relation.lhs, 2 * 3 * 7);
+        assert_eq!(relation.factors, [(2, 1), (3, 1), (7, 1)]);
+        assert_eq!(relation.ra, 101 * 103);
+        assert_eq!(relation.large_primes, (Integer::from(1), Integer::from(1)));
+        assert_eq!(graph.len(), 3);
+    }
+
+    #[test]
+    fn test_split_cofactor() {
+        let bounds = LargePrimeBounds {
+            prime: 2_000_000,
+            cofactor: 1 << 42,
+        };
+        let (p, q) = split_cofactor(Integer::from(1_000_003_u64 * 1_000_033), &bounds).unwrap();
+        let mut primes = vec![p, q];
+        primes.sort();
+        assert_eq!(primes, [1_000_003, 1_000_033]);
+        assert_eq!(
+            split_cofactor(Integer::from(1_000_003), &bounds),
+            Ok((Integer::from(1), Integer::from(1_000_003)))
+        );
+        assert_eq!(
+            split_cofactor(Integer::from(997_u64 * 1009), &bounds),
+            Err(Rejection::Composite)
+        );
+        assert_eq!(
+            split_cofactor(Integer::from(4_000_037), &bounds),
+            Err(Rejection::TooLarge)
+        );
+        assert_eq!(
+            split_cofactor(Integer::from(1_000_003_u64 * 3_000_017), &bounds),
+            Err(Rejection::TooLarge)
+        );
+
+        let mut stats = CofactorStats::default();
+        stats.reject(Rejection::TooLarge);
+        stats.reject(Rejection::Composite);
+        let mut total = CofactorStats {
+            single: 2,
+            ..CofactorStats::default()
+        };
+        total.merge(&stats);
+        total.merge(&stats);
+        assert_eq!((total.single, total.too_large, total.composite), (2, 2, 2));
+    }
+}
@@ -9,12 +9,39 @@ use rug::Integer;
 use crate::rabin_miller::is_rabin_miller_prime;
 
 pub mod algebra;
+pub mod backend;
+pub mod control;
+pub mod error;
+pub mod factorization;
+pub mod factorizer;
+pub mod kernel;
+pub mod lanczos;
+pub mod large_primes;
 pub mod memory_shared_MPQS;
 pub mod message_MPQS;
+pub mod multiplier;
+pub mod params;
+pub mod pm1;
+pub mod polynomial;
+pub mod preprocess;
 pub mod rabin_miller;
+pub mod relation;
+pub mod report;
+pub mod rho;
 pub mod serial_MPQS;
+pub mod sieve;
 pub mod tonelli_shanks;
 
+pub use control::{CancelHandle, Control};
+pub use error::FactorError;
+pub use factorization::Factorization;
+pub use factorizer::{Algorithm, FactorResult, Factorizer};
+pub use large_primes::CofactorStats;
+pub use params::Params;
+pub use pm1::Pm1Bounds;
+pub use polynomial::PolynomialKind;
+pub use report::{Progress, ProgressHook, RunReport, Timings};
+
 pub fn modular_inv(a0: Integer, m0: Integer) -> Integer {
     if m0 == 1 {
         return Integer::from(1);
@@ -23,7 +50,7 @@ pub fn modular_inv(a0: Integer, m0: Integer) -> Integer {
     let (mut a, mut m, mut x0, mut inv) = (a0, m0.clone(), Integer::new(), Integer::from(2));
 
     while a > 1 {
-        inv = inv - (a.clone() / &m) * &x0;
+        inv -= (a.clone() / &m) * &x0;
         a %= &m;
         std::mem::swap(&mut a, &mut m);
         std::mem::swap(&mut x0, &mut inv);
@@ -44,16 +71,17 @@ where
     ris
 }
 
-pub fn check_is_divisor(n: Integer, qs: Option<Integer>) {
+pub fn check_is_divisor(n: Integer, qs: Result<Integer, FactorError>) {
     match qs {
-        Some(qs) => {
+        Ok(qs) => {
             let (q, r) = n.clone().div_rem(qs.clone());
             assert_eq!(r, Integer::new());
             println!("{} = {} * {}", n, qs, q);
         }
-        None => {
+        Err(FactorError::InvalidInput(_)) => {
             assert!(is_rabin_miller_prime(&n));
             println!("{} is Rabin Miller prime", n);
         }
+        Err(error) => panic!("{} was not factored: {}", n, error),
     }
 }
@@ -51,13 +51,23 @@ pub fn main() {
             n
         );
     } else {
-        let r = match app.value_of("algorithm").unwrap() {
-            "S" => time(|| serial_MPQS::mpqs(&n)),
-            "M" => time(|| memory_shared_MPQS::mpqs(&n)),
-            "A" => time(|| message_MPQS::mpqs(&n)),
+        let algorithm = match app.value_of("algorithm").unwrap() {
+            "S" => Algorithm::Serial,
+            "M" => Algorithm::MemoryShared,
+            "A" => Algorithm::MessagePassing,
             _ => panic!(""),
         };
-        check_is_divisor(n, r);
+        let factorizer = Factorizer::new().algorithm(algorithm);
+        match time(|| factorizer.factorize(&n)) {
+            Ok(factorization) => {
+                assert!(factorization.verify(&n));
+                println!("{} = {}", n, factorization);
+            }
+            Err(error) => {
+                eprintln!("{} was not factored: {}", n, error);
+                std::process::exit(1);
+            }
+        }
     }
 }
 
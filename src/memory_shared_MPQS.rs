@@ -1,193 +1,114 @@
-use std::cmp::min;
-use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
-use std::sync::mpsc::SyncSender;
-use std::time::Duration;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, Sender};
 
-use chashmap::CHashMap;
-use crossbeam::queue::ArrayQueue;
 use rug::Integer;
-use rug::ops::Pow;
 
-use crate::algebra;
+use crate::backend::{self, Backend, Relations};
+use crate::control::Control;
+use crate::error::FactorError;
+use crate::kernel::{SieveContext, SieveKernel, Sieved};
+use crate::polynomial::{Family, PolynomialSeeder};
+use crate::report::RunReport;
 use crate::serial_MPQS::{initialize_qs, InitResult};
-use crate::tonelli_shanks::tonelli_shanks;
 
-pub fn mpqs(n: &Integer) -> Option<Integer> {
-    let InitResult {
-        roota,
-        factorbase,
-        tsqrt,
-        xmax,
-        tlog,
-        thresh,
-        min_prime,
-    } = initialize_qs(n);
-
-    let smooths = ArrayQueue::new(factorbase.len() + 100);
-
-    let (sender, receiver) = std::sync::mpsc::sync_channel(num_cpus::get());
-    let roota = Arc::new(Mutex::new(roota));
-
-    let arc_smooths = Arc::new(smooths);
-    let partials = Arc::new(CHashMap::default());
-
-    for _ in 0..num_cpus::get() {
-        let z = n.clone();
-        let factorbase = factorbase.clone();
-        let sender = sender.clone();
-        let tsqrt = tsqrt.clone();
-        let tlog = tlog.clone();
-        let arc_smooths = arc_smooths.clone();
-        let roota = roota.clone();
-        let partials = partials.clone();
-
-        std::thread::spawn(move || {
-            thread_loop(
-                z,
-                factorbase,
-                arc_smooths,
-                sender,
-                roota,
-                tsqrt,
-                tlog,
-                xmax,
-                min_prime,
-                thresh,
-                partials,
-            )
-        });
+pub fn mpqs(n: &Integer) -> Result<Integer, FactorError> {
+    mpqs_with(n, initialize_qs(n)?, num_cpus::get(), &Control::default())
+}
+
+/// Runs the sieve on `threads` threads with the settings in `init` until a factor is found or `control` stops the run
+pub fn mpqs_with(
+    n: &Integer,
+    init: InitResult,
+    threads: usize,
+    control: &Control,
+) -> Result<Integer, FactorError> {
+    let (factor, _) = backend::run(&MemoryShared { threads }, n, init, control)?;
+    Ok(factor)
+}
+
+/// Worker threads take the polynomials from a shared seeder
+/// and send the relations to the coordinator, which owns them
+pub struct MemoryShared {
+    pub threads: usize,
+}
+
+impl Backend for MemoryShared {
+    fn sieve(
+        &self,
+        n: &Integer,
+        context: Arc<SieveContext>,
+        seeder: PolynomialSeeder,
+        control: &Control,
+    ) -> Result<(Integer, RunReport), FactorError> {
+        let seeder = Mutex::new(seeder);
+        let stop = AtomicBool::new(false);
+
+        std::thread::scope(|scope| {
+            let (sender, receiver) = std::sync::mpsc::channel();
+            let workers = (0..self.threads)
+                .map(|_| {
+                    let sender = sender.clone();
+                    let (context, seeder, stop) = (&*context, &seeder, &stop);
+                    scope.spawn(move || thread_loop(context, sender, seeder, stop))
+                })
+                .collect();
+            drop(sender);
+
+            let factor = collect(n, &context.factorbase, &receiver, control);
+            stop.store(true, Ordering::Relaxed);
+            backend::join(workers, factor)
+        })
     }
+}
 
-    let _ = receiver.recv();
-    let mut new_smooth: Vec<_> = Vec::with_capacity(arc_smooths.len());
+/// Adds the relations sent by the workers to the store, trying the linear algebra after every polynomial
+fn collect(
+    n: &Integer,
+    factorbase: &[u64],
+    receiver: &Receiver<Sieved>,
+    control: &Control,
+) -> Result<(Integer, RunReport), FactorError> {
+    let mut relations = Relations::new(factorbase);
     loop {
-        while arc_smooths.is_empty() {
-            std::thread::sleep(Duration::from_millis(100));
-        }
-        while let Ok(t) = arc_smooths.pop() {
-            new_smooth.push(t);
-        }
-        if let Some(ris) = algebra::algebra(&factorbase, &new_smooth, n) {
-            return Some(ris);
+        let sieved = control.recv(receiver, &relations)?;
+        relations.add(sieved);
+        if let Some(factor) = relations.factor(n, factorbase) {
+            return Ok((factor, relations.report(control.start.elapsed())));
         }
     }
 }
 
+/// Sieves the families handed out by `seeder` until `stop` is set or another worker fails
 fn thread_loop(
-    n: Integer,
-    factorbase: Vec<u64>,
-    smooths: Arc<ArrayQueue<(Integer, (Integer, Integer))>>,
-    sender: SyncSender<()>,
-    roota: Arc<Mutex<Integer>>,
-    tsqrt: Vec<Integer>,
-    tlog: Vec<f64>,
-    xmax: i64,
-    min_prime: u64,
-    thresh: f64,
-    partials: Arc<CHashMap<Integer, (Integer, (Integer, Integer))>>,
+    context: &SieveContext,
+    sender: Sender<Sieved>,
+    seeder: &Mutex<PolynomialSeeder>,
+    stop: &AtomicBool,
 ) {
-    let sievesize = 1_i64 << 15;
+    let SieveContext {
+        kn,
+        factorbase,
+        tsqrt,
+        ..
+    } = context;
+    let mut kernel = SieveKernel::new(context);
 
     loop {
-        let my_roota: Integer = {
-            let mut aq_roota = roota.lock().unwrap();
-            aq_roota.next_prime_mut();
-            while n.legendre(&aq_roota) != 1 {
-                aq_roota.next_prime_mut();
-            }
-            aq_roota.clone()
+        let seed = match seeder.lock() {
+            Ok(mut seeder) => seeder.next_seed(kn, factorbase),
+            Err(_) => return,
         };
-        let a = my_roota.clone().pow(2);
-        let b = tonelli_shanks(&n, &my_roota);
-
-        let int2: Integer = b.clone() * 2;
-        let intermediate = int2.invert(&my_roota).expect("Inverse does not exist");
-        let b = (-(b.clone() * &b - &n) * intermediate + &b) % &a;
-
-        let c = (b.clone() * &b - &n) / &a;
-
-        let mut s1: HashMap<u64, i64> = HashMap::new();
-        let mut s2: HashMap<u64, i64> = HashMap::new();
-
-        for (i, p) in factorbase.iter().enumerate() {
-            let ainv = a
-                .clone()
-                .pow_mod(&Integer::from(p - 2), &Integer::from(*p))
-                .unwrap();
-            let mut sol1 = (tsqrt[i].clone() - &b) * &ainv % p;
-            let mut sol2 = (-tsqrt[i].clone() - &b) * &ainv % p;
-            sol1 -= ((sol1.clone() + xmax) / p) * p;
-            sol2 -= ((sol2.clone() + xmax) / p) * p;
-
-            s1.insert(*p, (sol1 + xmax).to_i64().unwrap());
-            s2.insert(*p, (sol2 + xmax).to_i64().unwrap());
-        }
-
-        for low in (-xmax..xmax + 1).step_by(sievesize as usize + 1) {
-            let high = min(xmax, low + sievesize);
-            let size = high - low;
-            let size_plus_1 = size + 1;
-
-            let mut S = vec![0_f64; size_plus_1 as usize];
-
-            for (i, p) in factorbase.iter().enumerate() {
-                if *p < min_prime {
-                    continue;
-                }
-                let mut sol1 = s1[p];
-                let mut sol2 = s2[p];
-                let logp = tlog[i];
-
-                let p_i64 = *p as i64;
-                while sol1 <= size || sol2 <= size {
-                    if sol1 <= size {
-                        S[sol1 as usize] += logp;
-                        sol1 += p_i64;
-                    }
-                    if sol2 <= size {
-                        S[sol2 as usize] += logp;
-                        sol2 += p_i64;
-                    }
-                }
-                s1.insert(*p, sol1 - size_plus_1);
-                s2.insert(*p, sol2 - size_plus_1);
+        for polynomial in Family::new(kn, factorbase, tsqrt, seed) {
+            if stop.load(Ordering::Relaxed) {
+                return;
             }
-
-            for i in 0..size_plus_1 {
-                if S[i as usize] > thresh {
-                    let x = i + low;
-                    let tofact: Integer = a.clone() * x.pow(2) + b.clone() * x * 2 + &c;
-                    let mut nf = tofact.clone().abs();
-
-                    for p in factorbase.iter() {
-                        while nf.clone() % p == 0 {
-                            nf /= p;
-                        }
-                    }
-
-                    if nf == 1 {
-                        smooths.push((a.clone() * x + &b, (tofact, my_roota.clone())));
-                    } else {
-                        match partials.remove(&nf) {
-                            Some((pairv, pairvals)) => {
-                                smooths.push((
-                                    pairv * (a.clone() * x + &b),
-                                    (tofact * pairvals.0, pairvals.1 * &my_roota * nf),
-                                ));
-                            }
-                            None => {
-                                partials
-                                    .insert(nf, (a.clone() * x + &b, (tofact, my_roota.clone())));
-                            }
-                        }
-                    }
-                }
+            let mut sieved = Sieved::default();
+            kernel.polynomial(&polynomial, &mut sieved);
+            if sender.send(sieved).is_err() {
+                return;
             }
         }
-        if smooths.len() > factorbase.len() {
-            sender.send(());
-        }
     }
 }
 
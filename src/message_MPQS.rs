@@ -1,200 +1,133 @@
-use std::cmp::min;
-use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, SyncSender};
 
 use rug::Integer;
-use rug::ops::Pow;
 
-use crate::algebra;
+use crate::backend::{self, Backend, Relations};
+use crate::control::Control;
+use crate::error::FactorError;
+use crate::kernel::{SieveContext, SieveKernel, Sieved};
+use crate::polynomial::{Family, FamilySeed, PolynomialSeeder};
+use crate::report::RunReport;
 use crate::serial_MPQS::{initialize_qs, InitResult};
-use crate::tonelli_shanks::tonelli_shanks;
 
 /// Nothing Shared
-pub fn mpqs(n: &Integer) -> Option<Integer> {
-    let InitResult {
-        mut roota,
-        factorbase,
-        tsqrt,
-        xmax,
-        tlog,
-        thresh,
-        min_prime,
-    } = initialize_qs(n);
-
-    // Multi Producer - Single Consumer
-    let (result_sender, result_receiver) = std::sync::mpsc::sync_channel(12);
-
-    for _ in 0..num_cpus::get() {
-        let z = n.clone();
-        let factorbase = factorbase.clone();
-        let result_sender = result_sender.clone();
-        let tsqrt = tsqrt.clone();
-        let tlog = tlog.clone();
-
-        std::thread::spawn(move || {
-            sieve_actor(
-                z,
-                factorbase,
-                result_sender,
-                tsqrt,
-                tlog,
-                xmax,
-                min_prime,
-                thresh,
-            )
-        });
-    }
+pub fn mpqs(n: &Integer) -> Result<Integer, FactorError> {
+    mpqs_with(n, initialize_qs(n)?, num_cpus::get(), &Control::default())
+}
 
-    let mut smooths = Vec::with_capacity(factorbase.len() + 100);
-    let mut partials: HashMap<Integer, (Integer, (Integer, Integer))> = HashMap::new();
+/// Runs the sieve on `threads` threads with the settings in `init` until a factor is found or `control` stops the run
+pub fn mpqs_with(
+    n: &Integer,
+    init: InitResult,
+    threads: usize,
+    control: &Control,
+) -> Result<Integer, FactorError> {
+    let (factor, _) = backend::run(&MessagePassing { threads }, n, init, control)?;
+    Ok(factor)
+}
 
-    loop {
-        let (mut sm, part, sender) = result_receiver.recv().unwrap();
-        smooths.append(&mut sm);
+/// Sieve actors ask the coordinator for a family of polynomials at a time
+/// and send back the relations they found in it
+pub struct MessagePassing {
+    pub threads: usize,
+}
 
-        for (key, (pairv2, pairvals2)) in part {
-            match partials.remove(&key) {
-                Some((pairv, pairvals)) => {
-                    smooths.push((
-                        pairv * pairv2,
-                        (pairvals2.0 * pairvals.0, pairvals.1 * pairvals2.1 * key),
-                    ));
+impl Backend for MessagePassing {
+    fn sieve(
+        &self,
+        n: &Integer,
+        context: Arc<SieveContext>,
+        mut seeder: PolynomialSeeder,
+        control: &Control,
+    ) -> Result<(Integer, RunReport), FactorError> {
+        let stop = AtomicBool::new(false);
+
+        std::thread::scope(|scope| {
+            // Multi Producer - Single Consumer
+            let (result_sender, result_receiver) = std::sync::mpsc::sync_channel(12);
+
+            let workers = (0..self.threads)
+                .map(|_| {
+                    let result_sender = result_sender.clone();
+                    let (context, stop) = (&*context, &stop);
+                    scope.spawn(move || sieve_actor(context, result_sender, stop))
+                })
+                .collect();
+            drop(result_sender);
+
+            let mut relations = Relations::new(&context.factorbase);
+            let factor = loop {
+                let (sieved, reply) = match control.recv(&result_receiver, &relations) {
+                    Ok(message) => message,
+                    Err(error) => break Err(error),
+                };
+                relations.add(sieved);
+                if let Some(factor) = relations.factor(n, &context.factorbase) {
+                    break Ok((factor, relations.report(control.start.elapsed())));
                 }
-                None => {
-                    partials.insert(key, (pairv2, pairvals2));
+                // The actor only drops its reply channel when it panics
+                if reply
+                    .send(seeder.next_seed(&context.kn, &context.factorbase))
+                    .is_err()
+                {
+                    break Err(FactorError::WorkerFailure);
                 }
-            }
-        }
-        if smooths.len() > factorbase.len() {
-            if let Some(ris) = algebra::algebra(&factorbase, &smooths, n) {
-                return Some(ris);
-            }
-        }
-        roota.next_prime_mut();
-        while n.legendre(&roota) != 1 {
-            roota.next_prime_mut();
-        }
-        sender.send(roota.clone());
+            };
+
+            // Actors waiting for a seed or for room in the channel see it closed
+            stop.store(true, Ordering::Relaxed);
+            drop(result_receiver);
+            backend::join(workers, factor)
+        })
     }
 }
 
+/// Sieves a family at a time, keeping a second request in flight so that it never waits for a seed,
+/// until `stop` is set or the coordinator closes the channels
 fn sieve_actor(
-    n: Integer,
-    factorbase: Vec<u64>,
-    sender: std::sync::mpsc::SyncSender<(
-        Vec<(Integer, (Integer, Integer))>,
-        HashMap<Integer, (Integer, (Integer, Integer))>,
-        std::sync::mpsc::SyncSender<Integer>,
-    )>,
-    tsqrt: Vec<Integer>,
-    tlog: Vec<f64>,
-    xmax: i64,
-    min_prime: u64,
-    thresh: f64,
+    context: &SieveContext,
+    sender: SyncSender<(Sieved, SyncSender<FamilySeed>)>,
+    stop: &AtomicBool,
 ) {
-    let sievesize = 1_i64 << 15;
-    let mut partials: HashMap<Integer, (Integer, (Integer, Integer))> = HashMap::new();
-    let mut smooths: Vec<(Integer, (Integer, Integer))> = Vec::new();
-
-    let (roota_sender, roota_receiver) = std::sync::mpsc::sync_channel(1);
-    sender.send((Vec::new(), HashMap::new(), roota_sender.clone()));
-    sender.send((Vec::new(), HashMap::new(), roota_sender.clone()));
-
-    loop {
-        let roota = roota_receiver.recv().unwrap();
-
-        let a = roota.clone().pow(2);
-        let b = tonelli_shanks(&n, &roota);
-
-        let int2: Integer = b.clone() * 2;
-        let intermediate = int2.invert(&roota).expect("Inverse does not exist");
-        let b = (-(b.clone() * &b - &n) * intermediate + &b) % &a;
-
-        let c = (b.clone() * &b - &n) / &a;
-
-        let mut s1: HashMap<u64, i64> = HashMap::new();
-        let mut s2: HashMap<u64, i64> = HashMap::new();
-
-        for (i, p) in factorbase.iter().enumerate() {
-            let ainv = a
-                .clone()
-                .pow_mod(&Integer::from(p - 2), &Integer::from(*p))
-                .unwrap();
-            let mut sol1 = (tsqrt[i].clone() - &b) * &ainv % p;
-            let mut sol2 = (-tsqrt[i].clone() - &b) * &ainv % p;
-            sol1 -= ((sol1.clone() + xmax) / p) * p;
-            sol2 -= ((sol2.clone() + xmax) / p) * p;
-
-            s1.insert(*p, (sol1 + xmax).to_i64().unwrap());
-            s2.insert(*p, (sol2 + xmax).to_i64().unwrap());
+    let SieveContext {
+        kn,
+        factorbase,
+        tsqrt,
+        ..
+    } = context;
+    let mut kernel = SieveKernel::new(context);
+
+    // Every request carries its own reply channel, dropped by the coordinator when it stops
+    let request = |sieved: Sieved| {
+        let (seed_sender, seed_receiver) = std::sync::mpsc::sync_channel(1);
+        sender
+            .send((sieved, seed_sender))
+            .ok()
+            .map(|_| seed_receiver)
+    };
+    let mut pending: VecDeque<Receiver<FamilySeed>> = VecDeque::new();
+    for _ in 0..2 {
+        match request(Sieved::default()) {
+            Some(reply) => pending.push_back(reply),
+            None => return,
         }
+    }
 
-        for low in (-xmax..xmax + 1).step_by(sievesize as usize + 1) {
-            let high = min(xmax, low + sievesize);
-            let size = high - low;
-            let size_plus_1 = size + 1;
-
-            let mut S = vec![0_f64; size_plus_1 as usize];
-
-            for (i, p) in factorbase.iter().enumerate() {
-                if *p < min_prime {
-                    continue;
-                }
-                let mut sol1 = s1[p];
-                let mut sol2 = s2[p];
-                let logp = tlog[i];
-
-                let p_i64 = *p as i64;
-                while sol1 < size_plus_1 || sol2 < size_plus_1 {
-                    if sol1 < size_plus_1 {
-                        S[sol1 as usize] += logp;
-                        sol1 += p_i64;
-                    }
-                    if sol2 < size_plus_1 {
-                        S[sol2 as usize] += logp;
-                        sol2 += p_i64;
-                    }
-                }
-                s1.insert(*p, sol1 - size_plus_1);
-                s2.insert(*p, sol2 - size_plus_1);
-            }
-
-            for i in 0..size_plus_1 {
-                if S[i as usize] > thresh {
-                    let x = i + low;
-                    let tofact: Integer = a.clone() * x.pow(2) + b.clone() * x * 2 + &c;
-                    let mut nf = tofact.clone().abs();
-
-                    for p in factorbase.iter() {
-                        while nf.clone() % p == 0 {
-                            nf /= p;
-                        }
-                    }
-
-                    if nf == 1 {
-                        smooths.push((a.clone() * x + &b, (tofact, roota.clone())));
-                    } else {
-                        match partials.remove(&nf) {
-                            Some((pairv, pairvals)) => {
-                                smooths.push((
-                                    pairv * (a.clone() * x + &b),
-                                    (tofact * pairvals.0, pairvals.1 * &roota * nf),
-                                ));
-                            }
-                            None => {
-                                partials.insert(nf, (a.clone() * x + &b, (tofact, roota.clone())));
-                            }
-                        }
-                    }
-                }
+    while let Some(Ok(seed)) = pending.pop_front().map(|reply| reply.recv()) {
+        let mut sieved = Sieved::default();
+        for polynomial in Family::new(kn, factorbase, tsqrt, seed) {
+            if stop.load(Ordering::Relaxed) {
+                return;
             }
+            kernel.polynomial(&polynomial, &mut sieved);
+        }
+        match request(sieved) {
+            Some(reply) => pending.push_back(reply),
+            None => return,
         }
-        if let Err(_) = sender.send((
-            smooths.drain(..).collect(),
-            partials.drain().collect(),
-            roota_sender.clone(),
-        )) {
-            return;
-        };
     }
 }
 
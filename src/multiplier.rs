@@ -0,0 +1,73 @@
+use primal_sieve;
+use rug::Integer;
+
+/// Odd square-free multipliers tried by `knuth_schroeppel`
+const MULTIPLIERS: [u64; 41] = [
+    1, 3, 5, 7, 11, 13, 15, 17, 19, 21, 23, 29, 31, 33, 35, 37, 39, 41, 43, 47, 51, 53, 55, 57, 59,
+    61, 65, 67, 69, 71, 73, 77, 79, 83, 85, 87, 89, 91, 93, 95, 97,
+];
+
+/// Primes used to score the multipliers
+const SCORE_BOUND: usize = 1000;
+
+/// Knuth–Schroeppel: the multiplier `k` for which `k n` has the densest factor base of small primes
+pub fn knuth_schroeppel(n: &Integer) -> u64 {
+    let primes: Vec<u64> = primal_sieve::Sieve::new(SCORE_BOUND)
+        .primes_from(3)
+        .take_while(|p| *p < SCORE_BOUND)
+        .map(|p| p as u64)
+        .collect();
+
+    MULTIPLIERS
+        .iter()
+        .filter(|k| n.clone().gcd_u(**k as u32) == 1)
+        .map(|k| (*k, score(n, *k, &primes)))
+        .fold(
+            (1, f64::MIN),
+            |best, (k, s)| if s > best.1 { (k, s) } else { best },
+        )
+        .0
+}
+
+/// Expected contribution of the small primes to the logarithm of a sieved value, minus the cost of the multiplier
+fn score(n: &Integer, k: u64, primes: &[u64]) -> f64 {
+    let kn: Integer = n.clone() * k;
+    let ln2 = 2_f64.ln();
+    let mut score = -0.5 * (k as f64).ln()
+        + match kn.mod_u(8) {
+            1 => 2_f64 * ln2,
+            5 => ln2,
+            3 | 7 => 0.5 * ln2,
+            _ => 0_f64,
+        };
+
+    for p in primes {
+        let logp = (*p as f64).ln();
+        if k.is_multiple_of(*p) {
+            score += logp / *p as f64;
+        } else if kn.legendre(&Integer::from(*p)) == 1 {
+            score += 2_f64 * logp / (*p - 1) as f64;
+        }
+    }
+    score
+}
+
+#[cfg(test)]
+mod tests {
+    use rug::Integer;
+
+    use super::*;
+
+    #[test]
+    fn test_knuth_schroeppel() {
+        let n = "2736300383840445596906210796102273501547527150973747"
+            .parse::<Integer>()
+            .unwrap();
+        assert_eq!(knuth_schroeppel(&n), 43);
+
+        let n = "523022617466601111760007224100074291200000001"
+            .parse::<Integer>()
+            .unwrap();
+        assert_eq!(knuth_schroeppel(&n), 1);
+    }
+}
@@ -0,0 +1,108 @@
+use crate::polynomial::PolynomialKind;
+
+/// Sieve parameters by number of digits of `kn`, interpolated linearly between rows:
+/// factor base size, half length of the sieve interval, large prime multiplier,
+/// exponent of the double large prime bound and threshold offset in bits.
+/// Double large primes slow serial runs down up to 40 digits and speed them up from 50 on,
+/// the large prime columns above 60 digits are extrapolated
+const TABLE: [(u32, usize, i64, u64, f64, f64); 10] = [
+    (10, 50, 12_000, 16, 0.0, 10.5),
+    (20, 150, 36_000, 16, 0.0, 17.0),
+    (30, 300, 72_000, 32, 0.0, 23.5),
+    (40, 500, 120_000, 32, 0.0, 30.0),
+    (50, 750, 180_000, 48, 1.7, 36.0),
+    (60, 1050, 250_000, 96, 1.8, 42.0),
+    (70, 1350, 325_000, 128, 1.85, 48.0),
+    (80, 1700, 410_000, 160, 1.9, 54.0),
+    (90, 2100, 510_000, 192, 1.9, 60.0),
+    (100, 2550, 615_000, 256, 1.9, 66.5),
+];
+
+/// Overrides of the parameter table, the values left to `None` are taken from the table
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Params {
+    /// Number of primes in the factor base, the prime 2 included
+    pub fb_size: Option<usize>,
+    /// The sieve interval of every polynomial is `[-xmax, xmax]`
+    pub xmax: Option<i64>,
+    /// Large primes are accepted up to this multiple of the largest factor base prime
+    pub large_prime_multiplier: Option<u64>,
+    /// Cofactors are split into two large primes up to the large prime bound to this power,
+    /// 0 disables the double large prime variation
+    pub cofactor_exponent: Option<f64>,
+    /// Positions are trial divided when their sum of logarithms is within this many bits
+    /// of the logarithm of the largest value of the polynomial
+    pub thresh_offset: Option<f64>,
+    /// Seed of the random choice of the SIQS coefficients, not part of the table
+    pub seed: u64,
+    /// How the polynomials are generated, not part of the table
+    pub polynomial: PolynomialKind,
+}
+
+/// Parameters of a run, from the table and the overrides
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tuning {
+    pub fb_size: usize,
+    pub xmax: i64,
+    pub large_prime_multiplier: u64,
+    pub cofactor_exponent: f64,
+    pub thresh_offset: f64,
+}
+
+impl Params {
+    /// Values for a number of `digits` digits, the table is clamped outside its range
+    pub fn tuning(&self, digits: u32) -> Tuning {
+        let next = TABLE.partition_point(|row| row.0 <= digits);
+        let (low, high) = match next {
+            0 => (TABLE[0], TABLE[0]),
+            n if n == TABLE.len() => (TABLE[n - 1], TABLE[n - 1]),
+            n => (TABLE[n - 1], TABLE[n]),
+        };
+        let t = if high.0 == low.0 {
+            0_f64
+        } else {
+            (digits - low.0) as f64 / (high.0 - low.0) as f64
+        };
+        let lerp = |a: f64, b: f64| a + (b - a) * t;
+
+        Tuning {
+            fb_size: self
+                .fb_size
+                .unwrap_or_else(|| lerp(low.1 as f64, high.1 as f64) as usize),
+            xmax: self
+                .xmax
+                .unwrap_or_else(|| lerp(low.2 as f64, high.2 as f64) as i64),
+            large_prime_multiplier: self
+                .large_prime_multiplier
+                .unwrap_or_else(|| lerp(low.3 as f64, high.3 as f64) as u64),
+            cofactor_exponent: self
+                .cofactor_exponent
+                .unwrap_or_else(|| lerp(low.4, high.4)),
+            thresh_offset: self.thresh_offset.unwrap_or_else(|| lerp(low.5, high.5)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tuning() {
+        let tuning = Params::default().tuning(45);
+        assert_eq!(tuning.fb_size, 625);
+        assert_eq!(tuning.xmax, 150_000);
+        assert_eq!(tuning.thresh_offset, 33.0);
+        assert_eq!(tuning.large_prime_multiplier, 40);
+        assert_eq!(tuning.cofactor_exponent, 0.85);
+        assert_eq!(Params::default().tuning(5), Params::default().tuning(10));
+        assert_eq!(Params::default().tuning(200).fb_size, 2550);
+
+        let params = Params {
+            xmax: Some(1000),
+            ..Params::default()
+        };
+        assert_eq!(params.tuning(45).xmax, 1000);
+        assert_eq!(params.tuning(45).fb_size, 625);
+    }
+}
@@ -0,0 +1,153 @@
+use rug::Integer;
+
+/// Spacing of the giant steps of stage 2, the baby steps are the residues coprime to it
+const GIANT_STEP: u64 = 2 * 3 * 5 * 7 * 11;
+
+/// Smoothness bounds of Pollard's p − 1: every prime power up to `b1`, then one more prime up to `b2`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Pm1Bounds {
+    pub b1: u64,
+    pub b2: u64,
+}
+
+impl Default for Pm1Bounds {
+    fn default() -> Pm1Bounds {
+        Pm1Bounds {
+            b1: 50_000,
+            b2: 5_000_000,
+        }
+    }
+}
+
+/// Nontrivial factor `p` of the composite `n` such that `p − 1` is smooth for `bounds`.
+/// `None` when there is none or every prime of `n` is found at once.
+/// `stop` is asked between two primes of stage 1 and two giant steps of stage 2,
+/// and gives `None` as well
+pub fn pm1<F: Fn() -> bool>(n: &Integer, bounds: &Pm1Bounds, stop: F) -> Option<Integer> {
+    let sieve = primal_sieve::Sieve::new(bounds.b1.max(bounds.b2) as usize);
+
+    let a = stage1(n, &sieve, bounds.b1, &stop)?;
+    let g = Integer::from(&a - 1).gcd(n);
+    if g != 1 {
+        return if g == *n { None } else { Some(g) };
+    }
+
+    let g = stage2(n, &a, &sieve, bounds.b1, bounds.b2, &stop)?.gcd(n);
+    if g != 1 && g != *n {
+        Some(g)
+    } else {
+        None
+    }
+}
+
+/// `2^E mod n`, where `E` is the product of the largest powers of the primes up to `b1` not above `b1`
+fn stage1<F: Fn() -> bool>(
+    n: &Integer,
+    sieve: &primal_sieve::Sieve,
+    b1: u64,
+    stop: &F,
+) -> Option<Integer> {
+    let mut a = Integer::from(2);
+    for p in sieve
+        .primes_from(2)
+        .map(|p| p as u64)
+        .take_while(|p| *p <= b1)
+    {
+        if stop() {
+            return None;
+        }
+        let mut power = p;
+        while power <= b1 / p {
+            power *= p;
+        }
+        a.pow_mod_mut(&Integer::from(power), n).unwrap();
+    }
+    Some(a)
+}
+
+/// Product modulo `n` of `a^(kD) − a^j` over the primes `q = kD − j` in `(b1, b2]`,
+/// which `p` divides when the order of `a` modulo `p` is `q`
+fn stage2<F: Fn() -> bool>(
+    n: &Integer,
+    a: &Integer,
+    sieve: &primal_sieve::Sieve,
+    b1: u64,
+    b2: u64,
+    stop: &F,
+) -> Option<Integer> {
+    let mut product = Integer::from(1);
+    if b2 <= b1 {
+        return Some(product);
+    }
+
+    let baby: Vec<Option<Integer>> = (0..GIANT_STEP)
+        .map(|j| {
+            if Integer::from(j).gcd_u(GIANT_STEP as u32) == 1 {
+                Some(a.clone().pow_mod(&Integer::from(j), n).unwrap())
+            } else {
+                None
+            }
+        })
+        .collect();
+    let step = a.clone().pow_mod(&Integer::from(GIANT_STEP), n).unwrap();
+
+    let mut k = (b1 + 1).div_ceil(GIANT_STEP);
+    let mut giant = a
+        .clone()
+        .pow_mod(&Integer::from(k * GIANT_STEP), n)
+        .unwrap();
+    for q in sieve
+        .primes_from(b1 as usize + 1)
+        .map(|q| q as u64)
+        .take_while(|q| *q <= b2)
+    {
+        while k * GIANT_STEP < q {
+            if stop() {
+                return None;
+            }
+            giant *= &step;
+            giant %= n;
+            k += 1;
+        }
+        // Primes dividing the giant step only matter below b1
+        if let Some(baby) = &baby[(k * GIANT_STEP - q) as usize] {
+            product *= Integer::from(&giant - baby);
+            product %= n;
+        }
+    }
+    Some(product)
+}
+
+#[cfg(test)]
+mod tests {
+    use rug::integer::IsPrime;
+    use rug::ops::Pow;
+
+    use super::*;
+    use crate::factorization::PRIME_REPS;
+
+    #[test]
+    fn test_pm1() {
+        let big = Integer::from(10).pow(30).next_prime();
+        // 2^31 - 2 = 2 * 3^2 * 7 * 11 * 31 * 151 * 331
+        let p = Integer::from(2_147_483_647);
+        let n = p.clone() * &big;
+        let stage1 = Pm1Bounds { b1: 1000, b2: 0 };
+        assert_eq!(pm1(&n, &stage1, || false), Some(p));
+
+        // p - 1 = 2 * k * q with k < 1000 and a prime q between b1 and b2
+        let q = Integer::from(50_000).next_prime();
+        let p = (1..1000_u32)
+            .map(|k| Integer::from(2 * k) * &q + 1)
+            .find(|p: &Integer| p.is_probably_prime(PRIME_REPS) != IsPrime::No)
+            .unwrap();
+        let n = p.clone() * &big;
+        assert_eq!(pm1(&n, &stage1, || false), None);
+        let stage2 = Pm1Bounds {
+            b1: 1000,
+            b2: 100_000,
+        };
+        assert_eq!(pm1(&n, &stage2, || false), Some(p));
+        assert_eq!(pm1(&n, &stage2, || true), None);
+    }
+}
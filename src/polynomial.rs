@@ -0,0 +1,535 @@
+use std::collections::HashSet;
+
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use rug::Integer;
+use rug::ops::Pow;
+
+use crate::relation::{Origin, Relation};
+use crate::sieve::NO_ROOT;
+use crate::tonelli_shanks::tonelli_shanks;
+
+/// Size of the primes we would like to multiply together to build a SIQS `a`
+const SIQS_PRIME_SIZE: f64 = 2000_f64;
+
+/// Random choices of `a` that may repeat a used one before the candidate window is widened
+const SIQS_ATTEMPTS: usize = 100;
+
+/// How the sieving polynomials `(ax + b)^2 - n = a(ax^2 + 2bx + c)` are generated
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PolynomialKind {
+    /// One polynomial for every prime `q`, with `a = q^2`
+    Mpqs,
+    /// `a` is a product of `s` factor base primes and gives `2^(s-1)` polynomials
+    #[default]
+    Siqs,
+}
+
+/// What a worker needs to build a family of polynomials
+#[derive(Clone, Debug)]
+pub enum FamilySeed {
+    Mpqs(Integer),
+    /// Indices in the factor base of the primes whose product is `a`
+    Siqs(Vec<usize>),
+}
+
+pub struct Polynomial {
+    pub a: Integer,
+    pub b: Integer,
+    pub c: Integer,
+    /// Factor that goes in the square root of the right hand side of every relation
+    pub ra: Integer,
+    /// Indices in the factor base of the primes that go in the right hand side of every relation
+    pub rhs_factors: Vec<usize>,
+    /// Roots of `ax^2 + 2bx + c` modulo every prime of the factor base,
+    /// `NO_ROOT` when the prime divides `a`
+    pub roots: Vec<(u32, u32)>,
+}
+
+impl Polynomial {
+    pub fn value(&self, x: i64) -> Integer {
+        self.a.clone() * x.pow(2) + self.b.clone() * x * 2 + &self.c
+    }
+
+    /// Relation at `x`, given the factor base primes and the large primes of `tofact = value(x)`
+    pub fn relation(
+        &self,
+        x: i64,
+        tofact: &Integer,
+        mut factors: Vec<(usize, u32)>,
+        large_primes: (Integer, Integer),
+    ) -> Relation {
+        for i in self.rhs_factors.iter() {
+            match factors.binary_search_by_key(i, |(j, _)| *j) {
+                Ok(k) => factors[k].1 += 1,
+                Err(k) => factors.insert(k, (*i, 1)),
+            }
+        }
+        Relation {
+            lhs: self.a.clone() * x + &self.b,
+            negative: *tofact < 0,
+            factors,
+            large_primes,
+            ra: self.ra.clone(),
+            origins: vec![Origin {
+                a: self.a.clone(),
+                b: self.b.clone(),
+                x,
+            }],
+        }
+    }
+}
+
+/// Hands out a new family of polynomials every time it is asked
+pub struct PolynomialSeeder {
+    state: SeederState,
+}
+
+enum SeederState {
+    Mpqs {
+        roota: Integer,
+    },
+    Siqs {
+        s: usize,
+        /// The candidates are the primes within a factor `spread` of `q`
+        q: f64,
+        spread: f64,
+        candidates: Vec<usize>,
+        target: Integer,
+        rng: Box<StdRng>,
+        used: HashSet<Vec<usize>>,
+    },
+}
+
+impl PolynomialSeeder {
+    /// Falls back to MPQS when `n` is too small to build `a` out of at least two primes,
+    /// `seed` drives the random choice of the SIQS coefficients
+    pub fn new(
+        kind: PolynomialKind,
+        n: &Integer,
+        factorbase: &[u64],
+        xmax: i64,
+        roota: Integer,
+        seed: u64,
+    ) -> PolynomialSeeder {
+        let siqs = match kind {
+            PolynomialKind::Siqs => siqs_state(n, factorbase, xmax, seed),
+            PolynomialKind::Mpqs => None,
+        };
+        PolynomialSeeder {
+            state: siqs.unwrap_or(SeederState::Mpqs { roota }),
+        }
+    }
+
+    pub fn kind(&self) -> PolynomialKind {
+        match self.state {
+            SeederState::Mpqs { .. } => PolynomialKind::Mpqs,
+            SeederState::Siqs { .. } => PolynomialKind::Siqs,
+        }
+    }
+
+    pub fn next_seed(&mut self, n: &Integer, factorbase: &[u64]) -> FamilySeed {
+        match &mut self.state {
+            SeederState::Mpqs { roota } => {
+                roota.next_prime_mut();
+                while n.legendre(roota) != 1 {
+                    roota.next_prime_mut();
+                }
+                FamilySeed::Mpqs(roota.clone())
+            }
+            SeederState::Siqs {
+                s,
+                q,
+                spread,
+                candidates,
+                target,
+                rng,
+                used,
+            } => loop {
+                for _ in 0..SIQS_ATTEMPTS {
+                    let chosen = choose_a_factors(*s, candidates, target, factorbase, rng);
+                    if used.insert(chosen.clone()) {
+                        return FamilySeed::Siqs(chosen);
+                    }
+                }
+                // The window is running out of unused products, it is widened,
+                // and once it spans the whole factor base `a` takes one more prime
+                let largest = *factorbase.last().unwrap() as f64;
+                if *q / *spread > 3_f64 || *q * *spread < largest {
+                    *spread *= 2_f64;
+                    *candidates = window(n, factorbase, *q, *spread);
+                } else if *s + 1 < candidates.len() {
+                    *s += 1;
+                }
+            },
+        }
+    }
+}
+
+fn siqs_state(n: &Integer, factorbase: &[u64], xmax: i64, seed: u64) -> Option<SeederState> {
+    let target: Integer = Integer::from(n * 2).sqrt() / xmax;
+    let log_target = target.to_f64().ln();
+    let largest = *factorbase.last()? as f64;
+    let prime_size = SIQS_PRIME_SIZE.min(largest / 4_f64);
+    if prime_size < 3_f64 {
+        return None;
+    }
+
+    let s = (log_target / prime_size.ln()).round() as usize;
+    if s < 2 {
+        return None;
+    }
+    let q = (log_target / s as f64).exp();
+    let spread = 2_f64;
+    let candidates = window(n, factorbase, q, spread);
+    if candidates.len() < s + 2 {
+        return None;
+    }
+
+    Some(SeederState::Siqs {
+        s,
+        q,
+        spread,
+        candidates,
+        target,
+        rng: Box::new(StdRng::seed_from_u64(seed)),
+        used: HashSet::new(),
+    })
+}
+
+/// Indices of the odd factor base primes between `q / spread` and `q * spread`,
+/// without the primes dividing `n`, which have a single root and would make `b` and `-b` give the same polynomials
+fn window(n: &Integer, factorbase: &[u64], q: f64, spread: f64) -> Vec<usize> {
+    factorbase
+        .iter()
+        .enumerate()
+        .skip(1)
+        .filter(|(_, p)| (**p as f64) >= q / spread && (**p as f64) <= q * spread)
+        .filter(|(_, p)| !n.is_divisible_u(**p as u32))
+        .map(|(i, _)| i)
+        .collect()
+}
+
+/// Picks `s - 1` random primes among the candidates, then the candidate that brings the product closest to `target`
+fn choose_a_factors(
+    s: usize,
+    candidates: &[usize],
+    target: &Integer,
+    factorbase: &[u64],
+    rng: &mut StdRng,
+) -> Vec<usize> {
+    let mut chosen: Vec<usize> = Vec::with_capacity(s);
+    let mut product = Integer::from(1);
+    while chosen.len() < s - 1 {
+        let i = candidates[rng.gen_range(0, candidates.len())];
+        if !chosen.contains(&i) {
+            product *= factorbase[i];
+            chosen.push(i);
+        }
+    }
+
+    let rest = Integer::from(target / &product)
+        .to_u64()
+        .unwrap_or(u64::MAX);
+    let last = *candidates
+        .iter()
+        .filter(|i| !chosen.contains(i))
+        .min_by_key(|i| (factorbase[**i] as i128 - rest as i128).abs())
+        .unwrap();
+    chosen.push(last);
+    chosen.sort();
+    chosen
+}
+
+/// Iterates over all the polynomials generated by a seed
+pub struct Family<'a> {
+    n: &'a Integer,
+    factorbase: &'a [u64],
+    tsqrt: &'a [u32],
+    state: FamilyState,
+}
+
+enum FamilyState {
+    Mpqs(Option<Integer>),
+    Siqs(SiqsFamily),
+}
+
+struct SiqsFamily {
+    a: Integer,
+    a_factors: Vec<usize>,
+    b: Integer,
+    bl: Vec<Integer>,
+    bainv2: Vec<Vec<u32>>,
+    signs: Vec<bool>,
+    roots: Vec<(u32, u32)>,
+    index: usize,
+}
+
+impl<'a> Family<'a> {
+    pub fn new(
+        n: &'a Integer,
+        factorbase: &'a [u64],
+        tsqrt: &'a [u32],
+        seed: FamilySeed,
+    ) -> Family<'a> {
+        let state = match seed {
+            FamilySeed::Mpqs(roota) => FamilyState::Mpqs(Some(roota)),
+            FamilySeed::Siqs(indices) => {
+                FamilyState::Siqs(SiqsFamily::new(n, factorbase, tsqrt, &indices))
+            }
+        };
+        Family {
+            n,
+            factorbase,
+            tsqrt,
+            state,
+        }
+    }
+}
+
+impl<'a> Iterator for Family<'a> {
+    type Item = Polynomial;
+
+    fn next(&mut self) -> Option<Polynomial> {
+        match &mut self.state {
+            FamilyState::Mpqs(roota) => roota
+                .take()
+                .and_then(|roota| mpqs_polynomial(self.n, self.factorbase, self.tsqrt, roota)),
+            FamilyState::Siqs(family) => family.next_polynomial(self.n, self.factorbase),
+        }
+    }
+}
+
+/// The polynomial with `a = roota^2`, `None` when `2b` has no inverse because `roota` divides `n`
+fn mpqs_polynomial(
+    n: &Integer,
+    factorbase: &[u64],
+    tsqrt: &[u32],
+    roota: Integer,
+) -> Option<Polynomial> {
+    let a = roota.clone().pow(2);
+    let b = tonelli_shanks(n, &roota);
+
+    let int2: Integer = b.clone() * 2;
+    let intermediate = int2.invert(&roota).ok()?;
+    let b = (-(b.clone() * &b - n) * intermediate + &b) % &a;
+
+    let c = (b.clone() * &b - n) / &a;
+
+    let roots = factorbase
+        .iter()
+        .zip(tsqrt)
+        .map(|(p, t)| {
+            let am = a.mod_u(*p as u32) as u64;
+            if am == 0 {
+                return (NO_ROOT, NO_ROOT);
+            }
+            roots_mod(
+                *t as u64,
+                b.mod_u(*p as u32) as u64,
+                inverse_mod(am, *p),
+                *p,
+            )
+        })
+        .collect();
+
+    Some(Polynomial {
+        a,
+        b,
+        c,
+        ra: roota,
+        rhs_factors: Vec::new(),
+        roots,
+    })
+}
+
+impl SiqsFamily {
+    fn new(n: &Integer, factorbase: &[u64], tsqrt: &[u32], indices: &[usize]) -> SiqsFamily {
+        let a: Integer = indices.iter().map(|i| factorbase[*i]).product();
+
+        let bl: Vec<Integer> = indices
+            .iter()
+            .map(|i| {
+                let q = factorbase[*i];
+                let a_q: Integer = a.clone() / q;
+                let inv = inverse_mod(a_q.mod_u(q as u32) as u64, q);
+                let mut gamma = tsqrt[*i] as u64 * inv % q;
+                if gamma > q / 2 {
+                    gamma = q - gamma;
+                }
+                a_q * gamma
+            })
+            .collect();
+        let b: Integer = bl.iter().sum();
+        debug_assert!((b.clone() * &b - n).is_divisible(&a));
+
+        let mut bainv2 = vec![Vec::with_capacity(factorbase.len()); bl.len()];
+        let roots = factorbase
+            .iter()
+            .zip(tsqrt)
+            .map(|(p, t)| {
+                let am = a.mod_u(*p as u32) as u64;
+                if am == 0 {
+                    bainv2.iter_mut().for_each(|v| v.push(0));
+                    return (NO_ROOT, NO_ROOT);
+                }
+                let ainv = inverse_mod(am, *p);
+                for (l, bl) in bl.iter().enumerate() {
+                    bainv2[l].push((2 * (bl.mod_u(*p as u32) as u64) % p * ainv % p) as u32);
+                }
+                roots_mod(*t as u64, b.mod_u(*p as u32) as u64, ainv, *p)
+            })
+            .collect();
+
+        SiqsFamily {
+            a,
+            a_factors: indices.to_vec(),
+            b,
+            signs: vec![true; bl.len()],
+            bl,
+            bainv2,
+            roots,
+            index: 0,
+        }
+    }
+
+    /// Moves along the Gray code: every polynomial differs from the previous one by a single `B_l`
+    fn next_polynomial(&mut self, n: &Integer, factorbase: &[u64]) -> Option<Polynomial> {
+        if self.index >= 1 << (self.bl.len() - 1) {
+            return None;
+        }
+        if self.index > 0 {
+            let v = self.index.trailing_zeros() as usize + 1;
+            let positive = self.signs[v];
+            self.signs[v] = !positive;
+            let delta: Integer = self.bl[v].clone() * 2;
+            if positive {
+                self.b -= delta;
+            } else {
+                self.b += delta;
+            }
+            for ((root, step), p) in self.roots.iter_mut().zip(&self.bainv2[v]).zip(factorbase) {
+                if root.0 != NO_ROOT {
+                    let p = *p as u32;
+                    let step = if positive { *step } else { p - step };
+                    root.0 = ((root.0 as u64 + step as u64) % p as u64) as u32;
+                    root.1 = ((root.1 as u64 + step as u64) % p as u64) as u32;
+                }
+            }
+        }
+        self.index += 1;
+
+        let c = (self.b.clone() * &self.b - n) / &self.a;
+        Some(Polynomial {
+            a: self.a.clone(),
+            b: self.b.clone(),
+            c,
+            ra: Integer::from(1),
+            rhs_factors: self.a_factors.clone(),
+            roots: self.roots.clone(),
+        })
+    }
+}
+
+/// Roots of `ax^2 + 2bx + c` modulo `p`, given `t^2 = n`, `bm = b` and `ainv = a^-1` modulo `p`
+pub fn roots_mod(t: u64, bm: u64, ainv: u64, p: u64) -> (u32, u32) {
+    let sol1 = (t + p - bm) % p * ainv % p;
+    let sol2 = (2 * p - t - bm) % p * ainv % p;
+    (sol1 as u32, sol2 as u32)
+}
+
+/// Inverse of `a` modulo `p`, `a` must be coprime to `p`
+pub fn inverse_mod(a: u64, p: u64) -> u64 {
+    let (mut old_r, mut r) = (a as i64 % p as i64, p as i64);
+    let (mut old_s, mut s) = (1_i64, 0_i64);
+    while r != 0 {
+        let q = old_r / r;
+        old_r -= q * r;
+        std::mem::swap(&mut old_r, &mut r);
+        old_s -= q * s;
+        std::mem::swap(&mut old_s, &mut s);
+    }
+    old_s.rem_euclid(p as i64) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use rug::Integer;
+
+    use crate::serial_MPQS::{initialize_qs, InitResult};
+
+    use super::*;
+
+    #[test]
+    fn test_siqs_roots() {
+        let n = "523022617466601111760007224100074291200000001"
+            .parse::<Integer>()
+            .unwrap();
+        let InitResult {
+            context,
+            mut seeder,
+        } = initialize_qs(&n).unwrap();
+        assert_eq!(seeder.kind(), PolynomialKind::Siqs);
+
+        let kn = context.kn.clone();
+        let seed = seeder.next_seed(&kn, &context.factorbase);
+        let mut count = 0;
+        for polynomial in Family::new(&kn, &context.factorbase, &context.tsqrt, seed) {
+            assert_eq!(
+                polynomial.b.clone().square() - &kn,
+                polynomial.c.clone() * &polynomial.a
+            );
+            for (p, (sol1, sol2)) in context.factorbase.iter().zip(&polynomial.roots).skip(1) {
+                if *sol1 != NO_ROOT {
+                    assert!(polynomial.value(*sol1 as i64).is_divisible_u(*p as u32));
+                    assert!(polynomial.value(*sol2 as i64).is_divisible_u(*p as u32));
+                }
+            }
+            count += 1;
+        }
+        assert!(count > 1);
+
+        // Seeds never repeat, even once a small factor base runs out of products in the first window
+        let n = "1000000016000000063".parse::<Integer>().unwrap();
+        let InitResult {
+            context,
+            mut seeder,
+        } = initialize_qs(&n).unwrap();
+        assert_eq!(seeder.kind(), PolynomialKind::Siqs);
+        let mut seen = HashSet::new();
+        for _ in 0..2000 {
+            match seeder.next_seed(&context.kn, &context.factorbase) {
+                FamilySeed::Siqs(chosen) => assert!(seen.insert(chosen)),
+                FamilySeed::Mpqs(_) => unreachable!(),
+            }
+        }
+    }
+
+    #[test]
+    fn test_multiplier_primes() {
+        let n = "676292275716558246502605230897191366469551764092181362779759"
+            .parse::<Integer>()
+            .unwrap();
+        let InitResult {
+            context,
+            mut seeder,
+        } = initialize_qs(&n).unwrap();
+        let kn = context.kn.clone();
+        assert_eq!(Integer::from(&kn / &n), 79);
+        assert!(context.factorbase.contains(&79));
+
+        // A prime of the multiplier in `a` would give every polynomial twice, as `b` and `-b`
+        for _ in 0..200 {
+            let seed = seeder.next_seed(&kn, &context.factorbase);
+            if let FamilySeed::Siqs(chosen) = &seed {
+                assert!(chosen
+                    .iter()
+                    .all(|i| !kn.is_divisible_u(context.factorbase[*i] as u32)));
+            }
+            let mut seen = HashSet::new();
+            for polynomial in Family::new(&kn, &context.factorbase, &context.tsqrt, seed) {
+                assert!(seen.insert(polynomial.b.clone().abs()));
+            }
+        }
+    }
+}
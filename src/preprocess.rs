@@ -0,0 +1,83 @@
+use rug::Integer;
+use rug::ops::Pow;
+
+use crate::factorization::Factorization;
+
+/// Primes below this bound are removed by trial division before any other method
+pub const TRIAL_BOUND: u64 = 1 << 16;
+
+/// Removes the primes below `bound` from `n`, returns them with the cofactor left
+pub fn trial_division(n: &Integer, bound: u64) -> (Factorization, Integer) {
+    let mut factorization = Factorization::new();
+    let mut rest = n.clone();
+    for p in primal_sieve::Primes::all()
+        .map(|p| p as u64)
+        .take_while(|p| *p < bound)
+    {
+        if p * p > rest {
+            break;
+        }
+        let mut exponent = 0;
+        while rest.is_divisible_u(p as u32) {
+            rest /= p;
+            exponent += 1;
+        }
+        if exponent > 0 {
+            factorization.add(Integer::from(p), exponent);
+        }
+    }
+    if rest > 1 && rest < bound {
+        factorization.add(rest, 1);
+        rest = Integer::from(1);
+    }
+    (factorization, rest)
+}
+
+/// `(root, k)` with `n = root^k` and the largest `k > 1`, `None` when `n` is not a perfect power
+pub fn perfect_power(n: &Integer) -> Option<(Integer, u32)> {
+    if *n < 4 || !n.is_perfect_power() {
+        return None;
+    }
+    let bits = n.significant_bits();
+    for k in primal_sieve::Primes::all()
+        .map(|k| k as u32)
+        .take_while(|k| *k < bits)
+    {
+        let root = n.clone().root(k);
+        if root.clone().pow(k) == *n {
+            let (root, j) = perfect_power(&root).unwrap_or((root, 1));
+            return Some((root, k * j));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_preprocess() {
+        let big = Integer::from(1_000_000_007);
+        let n = Integer::from(2).pow(5) * 3 * 65537 * &big;
+        let (factorization, rest) = trial_division(&n, TRIAL_BOUND);
+        assert_eq!(factorization.to_string(), "2^5 * 3");
+        assert_eq!(rest, big.clone() * 65537);
+
+        let (factorization, rest) = trial_division(&Integer::from(2 * 65521), TRIAL_BOUND);
+        assert_eq!(factorization.to_string(), "2 * 65521");
+        assert_eq!(rest, 1);
+
+        assert_eq!(
+            perfect_power(&Integer::from(3).pow(10)),
+            Some((Integer::from(3), 10))
+        );
+        assert_eq!(
+            perfect_power(&Integer::from(36).pow(3)),
+            Some((Integer::from(6), 6))
+        );
+        assert_eq!(perfect_power(&big.clone().pow(3)), Some((big.clone(), 3)));
+        assert_eq!(perfect_power(&(big.clone().pow(2) * 3)), None);
+        assert_eq!(perfect_power(&Integer::from(1)), None);
+    }
+}
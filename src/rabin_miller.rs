@@ -14,7 +14,7 @@ pub fn is_rabin_miller_prime(n: &Integer) -> bool {
         s += 1;
     }
 
-    for a in primes_to_test(&n)
+    for a in primes_to_test(n)
         .iter()
         .map(|a| Integer::from(*a))
         .filter(|a| a.clone() + 1 < *n)
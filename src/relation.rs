@@ -0,0 +1,100 @@
+use rug::Integer;
+
+/// Polynomial `(ax + b)^2 - n` and position `x` where a relation was sieved
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Origin {
+    pub a: Integer,
+    pub b: Integer,
+    pub x: i64,
+}
+
+/// `lhs^2 = (-1)^negative * prod(factorbase[i]^e) * large primes * ra^2 (mod n)`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Relation {
+    pub lhs: Integer,
+    pub negative: bool,
+    /// Indices in the factor base and exponents of the primes of the right hand side, sorted by index
+    pub factors: Vec<(usize, u32)>,
+    /// Large primes left after dividing out the factor base, `1` for a missing one
+    pub large_primes: (Integer, Integer),
+    /// Factor known to appear squared in the right hand side
+    pub ra: Integer,
+    /// More than one for relations combined from partials
+    pub origins: Vec<Origin>,
+}
+
+impl Relation {
+    /// Multiplies `other` into this relation, the large primes are left to the caller
+    pub fn combine(&mut self, other: &Relation) {
+        self.lhs *= &other.lhs;
+        self.negative ^= other.negative;
+        self.ra *= &other.ra;
+        self.origins.extend(other.origins.iter().cloned());
+
+        let mut factors = Vec::with_capacity(self.factors.len() + other.factors.len());
+        let (mut left, mut right) = (
+            self.factors.iter().peekable(),
+            other.factors.iter().peekable(),
+        );
+        loop {
+            let next = match (left.peek(), right.peek()) {
+                (Some((i, e)), Some((j, f))) if i == j => {
+                    left.next();
+                    right.next();
+                    (*i, e + f)
+                }
+                (Some((i, _)), Some((j, _))) if i < j => *left.next().unwrap(),
+                (Some(_), None) => *left.next().unwrap(),
+                (_, Some(_)) => *right.next().unwrap(),
+                (None, None) => break,
+            };
+            factors.push(next);
+        }
+        self.factors = factors;
+    }
+
+    /// Rows of the matrix with an odd exponent: 0 is the sign, `i + 1` is `factorbase[i]`
+    pub fn rows(&self) -> Vec<u32> {
+        let sign = if self.negative { Some(0) } else { None };
+        sign.into_iter()
+            .chain(
+                self.factors
+                    .iter()
+                    .filter(|(_, e)| e & 1 == 1)
+                    .map(|(i, _)| *i as u32 + 1),
+            )
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rug::Integer;
+
+    use super::*;
+
+    #[test]
+    fn test_combine() {
+        let relation = |lhs: i64, negative: bool, factors: Vec<(usize, u32)>| Relation {
+            lhs: Integer::from(lhs),
+            negative,
+            factors,
+            large_primes: (Integer::from(1), Integer::from(1)),
+            ra: Integer::from(1),
+            origins: vec![Origin {
+                a: Integer::from(1),
+                b: Integer::from(0),
+                x: lhs,
+            }],
+        };
+        let mut r = relation(3, true, vec![(0, 1), (2, 3), (5, 1)]);
+        assert_eq!(r.rows(), [0, 1, 3, 6]);
+
+        r.combine(&relation(7, true, vec![(1, 2), (2, 1), (6, 1)]));
+        assert_eq!(r.lhs, 21);
+        assert!(!r.negative);
+        assert_eq!(r.factors, [(0, 1), (1, 2), (2, 4), (5, 1), (6, 1)]);
+        assert_eq!(r.rows(), [1, 6, 7]);
+        assert_eq!(r.origins.len(), 2);
+    }
+}
@@ -0,0 +1,108 @@
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::large_primes::CofactorStats;
+
+/// State of a run, handed to the progress hook and returned when a run stops early
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Progress {
+    pub polynomials: usize,
+    /// Relations the sieve found smooth over the factor base
+    pub full: usize,
+    /// Smooth relations combined from partials that close a cycle
+    pub combined: usize,
+    /// Partial relations waiting for a cycle
+    pub partials: usize,
+    /// Smooth relations needed before the linear algebra is tried
+    pub target: usize,
+    /// Cofactors kept as large prime partials or rejected
+    pub cofactors: CofactorStats,
+    pub elapsed: Duration,
+}
+
+impl Progress {
+    /// Smooth relations, full and combined
+    pub fn relations(&self) -> usize {
+        self.full + self.combined
+    }
+
+    /// Smooth relations per second
+    pub fn rate(&self) -> f64 {
+        self.relations() as f64 / self.elapsed.as_secs_f64().max(f64::MIN_POSITIVE)
+    }
+}
+
+impl fmt::Display for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}/{} relations ({} full, {} combined, {} partials) from {} polynomials after {:?}",
+            self.relations(),
+            self.target,
+            self.full,
+            self.combined,
+            self.partials,
+            self.polynomials,
+            self.elapsed
+        )
+    }
+}
+
+/// Callback given the progress of a run at most once every `interval`
+#[derive(Clone)]
+pub struct ProgressHook {
+    pub interval: Duration,
+    callback: Arc<dyn Fn(&Progress) + Send + Sync>,
+}
+
+impl ProgressHook {
+    pub fn new<F>(interval: Duration, callback: F) -> ProgressHook
+    where
+        F: Fn(&Progress) + Send + Sync + 'static,
+    {
+        ProgressHook {
+            interval,
+            callback: Arc::new(callback),
+        }
+    }
+
+    pub fn call(&self, progress: &Progress) {
+        (self.callback)(progress)
+    }
+}
+
+impl fmt::Debug for ProgressHook {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ProgressHook")
+            .field("interval", &self.interval)
+            .finish()
+    }
+}
+
+/// Time spent in every phase of a run, the failed attempts at the linear algebra included
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Timings {
+    /// Factor base and sieve parameters
+    pub init: Duration,
+    pub sieve: Duration,
+    /// Removal of the relations that cannot be part of a dependency
+    pub filtering: Duration,
+    pub linear_algebra: Duration,
+    /// Square roots and gcds of the dependencies
+    pub square_root: Duration,
+}
+
+impl Timings {
+    /// Filtering, linear algebra and square root together
+    pub fn algebra(&self) -> Duration {
+        self.filtering + self.linear_algebra + self.square_root
+    }
+}
+
+/// Summary of a run that found a factor
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RunReport {
+    pub progress: Progress,
+    pub timings: Timings,
+}
@@ -0,0 +1,169 @@
+use std::cmp::min;
+
+use rug::Integer;
+
+/// Differences multiplied together modulo `n` before taking a gcd with `n`
+const BATCH: u64 = 128;
+
+/// Constants `c` of `x -> x^2 + c` tried when a walk closes its cycle modulo every prime of `n` at once
+const CONSTANTS: u64 = 4;
+
+/// Numbers a walk is done with, machine words for the cofactors of the sieve and `Integer` otherwise
+pub trait Residue: Clone + PartialEq {
+    fn from_u64(value: u64) -> Self;
+
+    fn is_even(&self) -> bool;
+
+    /// `self^2 + c` modulo `n`
+    fn step(&mut self, c: u64, n: &Self);
+
+    /// `self * |x - y|` modulo `n`
+    fn mul_difference(&mut self, x: &Self, y: &Self, n: &Self);
+
+    fn gcd(&self, n: &Self) -> Self;
+}
+
+impl Residue for u64 {
+    fn from_u64(value: u64) -> u64 {
+        value
+    }
+
+    fn is_even(&self) -> bool {
+        self & 1 == 0
+    }
+
+    fn step(&mut self, c: u64, n: &u64) {
+        *self = ((*self as u128 * *self as u128 + c as u128) % *n as u128) as u64;
+    }
+
+    fn mul_difference(&mut self, x: &u64, y: &u64, n: &u64) {
+        *self = (*self as u128 * x.abs_diff(*y) as u128 % *n as u128) as u64;
+    }
+
+    fn gcd(&self, n: &u64) -> u64 {
+        let (mut a, mut b) = (*self, *n);
+        while b != 0 {
+            a %= b;
+            std::mem::swap(&mut a, &mut b);
+        }
+        a
+    }
+}
+
+impl Residue for Integer {
+    fn from_u64(value: u64) -> Integer {
+        Integer::from(value)
+    }
+
+    fn is_even(&self) -> bool {
+        Integer::is_even(self)
+    }
+
+    fn step(&mut self, c: u64, n: &Integer) {
+        self.square_mut();
+        *self += c;
+        *self %= n;
+    }
+
+    fn mul_difference(&mut self, x: &Integer, y: &Integer, n: &Integer) {
+        *self *= Integer::from(x - y).abs();
+        *self %= n;
+    }
+
+    fn gcd(&self, n: &Integer) -> Integer {
+        self.clone().gcd(n)
+    }
+}
+
+/// Nontrivial factor of the composite `n` by Pollard's rho with Brent's cycle detection.
+/// `None` when the walk of a constant runs past `max_iterations` or no constant gives one.
+/// `stop` is asked between two batches and gives `None` as well
+pub fn rho<R: Residue, F: Fn() -> bool>(n: &R, max_iterations: u64, stop: F) -> Option<R> {
+    if n.is_even() {
+        return Some(R::from_u64(2));
+    }
+    for c in 1..=CONSTANTS {
+        let g = brent(n, c, max_iterations, &stop)?;
+        if g != *n {
+            return Some(g);
+        }
+    }
+    None
+}
+
+/// One walk of `x -> x^2 + c` modulo `n`, the cycle is looked for over windows of doubling length,
+/// `n` itself when the cycle closes modulo every prime of `n` at once
+fn brent<R: Residue, F: Fn() -> bool>(n: &R, c: u64, max_iterations: u64, stop: &F) -> Option<R> {
+    let one = R::from_u64(1);
+    let mut y = R::from_u64(2);
+    let mut x = y.clone();
+    let mut saved = y.clone();
+    let mut product = one.clone();
+    let mut g = one.clone();
+    let mut r = 1;
+    let mut iterations = 0;
+
+    while g == one {
+        if iterations >= max_iterations {
+            return None;
+        }
+        x.clone_from(&y);
+        for _ in 0..r {
+            y.step(c, n);
+        }
+        let mut k = 0;
+        while k < r && g == one {
+            if stop() {
+                return None;
+            }
+            saved.clone_from(&y);
+            for _ in 0..min(BATCH, r - k) {
+                y.step(c, n);
+                product.mul_difference(&x, &y, n);
+            }
+            g = product.gcd(n);
+            k += BATCH;
+        }
+        iterations += 2 * r;
+        r *= 2;
+    }
+
+    // The batch that gave `n` may hide a factor, it is walked again one gcd at a time
+    if g == *n {
+        loop {
+            saved.step(c, n);
+            let mut difference = one.clone();
+            difference.mul_difference(&x, &saved, n);
+            g = difference.gcd(n);
+            if g != one {
+                break;
+            }
+        }
+    }
+    Some(g)
+}
+
+#[cfg(test)]
+mod tests {
+    use rug::ops::Pow;
+
+    use super::*;
+
+    #[test]
+    fn test_rho() {
+        let n = Integer::from(99991_u64 * 99877);
+        let factor = rho(&n, 1 << 16, || false).unwrap();
+        assert!(factor == 99991 || factor == 99877);
+        let factor = rho(&(99991_u64 * 99877), 1 << 16, || false).unwrap();
+        assert!(factor == 99991 || factor == 99877);
+
+        let big = Integer::from(10).pow(30).next_prime();
+        let n = big.clone() * 1_000_003;
+        assert_eq!(rho(&n, 1 << 16, || false), Some(Integer::from(1_000_003)));
+        assert_eq!(rho(&n, 1 << 16, || true), None);
+        assert_eq!(rho(&(n * 2), 1 << 16, || false), Some(Integer::from(2)));
+
+        let n = big.clone() * big.next_prime();
+        assert_eq!(rho(&n, 1 << 10, || false), None);
+    }
+}
@@ -1,170 +1,153 @@
-use std::cmp::{max, min};
-use std::collections::HashMap;
+use std::cmp::max;
+use std::sync::Arc;
 
 use primal_sieve;
 use rug::Integer;
+use rug::integer::IsPrime;
+
+use crate::backend::{self, Backend, Relations};
+use crate::control::Control;
+use crate::error::FactorError;
+use crate::factorization::PRIME_REPS;
+use crate::kernel::{SieveContext, SieveKernel, Sieved};
+use crate::large_primes::LargePrimeBounds;
+use crate::multiplier::knuth_schroeppel;
+use crate::params::Params;
+use crate::polynomial::{Family, PolynomialSeeder};
+use crate::report::RunReport;
+use crate::sieve::{log2, prime_powers};
+use crate::tonelli_shanks::sqrt_mod;
+
+pub fn mpqs(n: &Integer) -> Result<Integer, FactorError> {
+    mpqs_with(n, initialize_qs(n)?, &Control::default())
+}
 
-use crate::algebra;
-use crate::tonelli_shanks::tonelli_shanks;
-
-pub fn mpqs(n: &Integer) -> Option<Integer> {
-    let InitResult {
-        mut roota,
-        factorbase,
-        tsqrt,
-        xmax,
-        tlog,
-        thresh,
-        min_prime,
-    } = initialize_qs(n);
+/// Runs the sieve with the settings in `init`, which can be tuned after `initialize_qs`,
+/// until a factor is found or `control` stops the run
+pub fn mpqs_with(n: &Integer, init: InitResult, control: &Control) -> Result<Integer, FactorError> {
+    let (factor, _) = backend::run(&Serial, n, init, control)?;
+    Ok(factor)
+}
 
-    let mut smooths = Vec::new();
-    let mut partials: HashMap<Integer, (Integer, (Integer, Integer))> = HashMap::new();
-    let sievesize = 1_i64 << 15;
+/// Sieves one polynomial after the other on the calling thread
+pub struct Serial;
+
+impl Backend for Serial {
+    fn sieve(
+        &self,
+        n: &Integer,
+        context: Arc<SieveContext>,
+        mut seeder: PolynomialSeeder,
+        control: &Control,
+    ) -> Result<(Integer, RunReport), FactorError> {
+        let SieveContext {
+            kn,
+            factorbase,
+            tsqrt,
+            ..
+        } = &*context;
+        let mut kernel = SieveKernel::new(&context);
+        let mut relations = Relations::new(factorbase);
 
-    loop {
         loop {
-            roota.next_prime_mut();
-            if n.legendre(&roota) == 1 {
-                break;
-            }
-        }
-        let a = roota.clone() * &roota;
-        let b = tonelli_shanks(&n, &roota);
-
-        let int2: Integer = b.clone() * 2;
-        let intermediate = int2.invert(&roota).unwrap();
-
-        let b = (b.clone() - (b.clone() * b - n) * intermediate) % &a;
-
-        let c = (b.clone() * &b - n) / &a;
-
-        let mut s1: HashMap<u64, i64> = HashMap::new();
-        let mut s2: HashMap<u64, i64> = HashMap::new();
-
-        for (i, p) in factorbase.iter().enumerate() {
-            let ainv = a
-                .clone()
-                .pow_mod(&Integer::from(p - 2), &Integer::from(*p))
-                .unwrap();
-            let mut sol1 = (tsqrt[i].clone() - &b) * &ainv % p;
-            let mut sol2 = (-tsqrt[i].clone() - &b) * &ainv % p;
-            sol1 -= ((sol1.clone() + xmax) / p) * p;
-            sol2 -= ((sol2.clone() + xmax) / p) * p;
-
-            s1.insert(*p, (sol1 + xmax).to_i64().unwrap());
-            s2.insert(*p, (sol2 + xmax).to_i64().unwrap());
-        }
-
-        for low in (-xmax..xmax + 1).step_by(sievesize as usize + 1) {
-            let high = min(xmax, low + sievesize);
-            let size = high - low;
-            let size_plus_1 = size + 1;
-
-            let mut S = vec![0_f64; size_plus_1 as usize];
-
-            for (i, p) in factorbase.iter().enumerate() {
-                if *p < min_prime {
-                    continue;
-                }
-                let mut sol1 = s1[p];
-                let mut sol2 = s2[p];
-                let logp = tlog[i];
-
-                let p_i64 = *p as i64;
-                while sol1 <= size || sol2 <= size {
-                    if sol1 <= size {
-                        S[sol1 as usize] += logp;
-                        sol1 += p_i64;
-                    }
-                    if sol2 <= size {
-                        S[sol2 as usize] += logp;
-                        sol2 += p_i64;
-                    }
-                }
-                s1.insert(*p, sol1 - size_plus_1);
-                s2.insert(*p, sol2 - size_plus_1);
-            }
-
-            for i in 0..size_plus_1 {
-                if S[i as usize] > thresh {
-                    let x = i + low;
-                    let tofact: Integer = a.clone() * x.pow(2) + b.clone() * x * 2 + &c;
-                    let mut nf = tofact.clone().abs();
-
-                    for p in factorbase.iter() {
-                        while nf.clone() % p == 0 {
-                            nf /= p;
-                        }
-                    }
-                    if nf == 1 {
-                        smooths.push((a.clone() * x + &b, (tofact, roota.clone())));
-                    } else {
-                        match partials.remove(&nf) {
-                            Some((pairv, pairvals)) => {
-                                smooths.push((
-                                    pairv * (a.clone() * x + &b),
-                                    (tofact * pairvals.0, pairvals.1 * &roota * nf),
-                                ));
-                            }
-                            None => {
-                                partials.insert(nf, (a.clone() * x + &b, (tofact, roota.clone())));
-                            }
-                        }
-                    }
+            let seed = seeder.next_seed(kn, factorbase);
+            for polynomial in Family::new(kn, factorbase, tsqrt, seed) {
+                control.check(&relations)?;
+                let mut sieved = Sieved::default();
+                kernel.polynomial(&polynomial, &mut sieved);
+                relations.add(sieved);
+                if let Some(factor) = relations.factor(n, factorbase) {
+                    return Ok((factor, relations.report(control.start.elapsed())));
                 }
             }
         }
-        if smooths.len() > factorbase.len() {
-            if let Some(ris) = algebra::algebra(&factorbase, &smooths, n) {
-                return Some(ris);
-            }
-        }
     }
 }
 
+/// Exponent of 2 in `(ax + b)^2 - kn` when `ax + b` is odd, which only depends on `kn` modulo 8
+fn two_adic_log(kn: &Integer) -> u8 {
+    match kn.mod_u(8) {
+        1 => 3,
+        5 => 2,
+        _ => 1,
+    }
+}
+
+/// A prime of the factor base that divides `n`, found before sieving
+pub fn factor_base_divisor(n: &Integer, factorbase: &[u64]) -> Option<Integer> {
+    factorbase
+        .iter()
+        .find(|p| n.is_divisible_u(**p as u32) && *n != **p)
+        .map(|p| Integer::from(*p))
+}
+
+/// Settings of a run, the sieve context shared by the threads and the polynomial seeder
 pub struct InitResult {
-    pub roota: Integer,
-    pub factorbase: Vec<u64>,
-    pub tsqrt: Vec<Integer>,
-    pub xmax: i64,
-    pub tlog: Vec<f64>,
-    pub thresh: f64,
-    pub min_prime: u64,
+    pub context: SieveContext,
+    pub seeder: PolynomialSeeder,
 }
 
-pub fn initialize_qs(n: &Integer) -> InitResult {
-    let _root2n: Integer = (n * Integer::from(2)).sqrt();
+pub fn initialize_qs(n: &Integer) -> Result<InitResult, FactorError> {
+    initialize_with(n, &Params::default())
+}
 
-    let bound: usize = (n.to_f64().log10().powi(2) * 5_f64) as usize;
+/// Builds the factor base and the settings of the sieve from the parameter table and the overrides in `params`
+pub fn initialize_with(n: &Integer, params: &Params) -> Result<InitResult, FactorError> {
+    if *n < 4 || n.is_probably_prime(PRIME_REPS) != IsPrime::No {
+        return Err(FactorError::InvalidInput(format!("{} is not composite", n)));
+    }
+    let multiplier = knuth_schroeppel(n);
+    let kn: Integer = n.clone() * multiplier;
+    let n = &kn;
+    let tuning = params.tuning(n.to_string().len() as u32);
+    if tuning.fb_size < 2 {
+        return Err(FactorError::InvalidInput(
+            "the factor base needs at least two primes".to_owned(),
+        ));
+    }
+    if tuning.xmax < 1 {
+        return Err(FactorError::InvalidInput(
+            "the sieve interval must not be empty".to_owned(),
+        ));
+    }
+    // The values of the polynomials are computed from x^2 in an i64
+    if tuning.xmax > i32::MAX as i64 {
+        return Err(FactorError::ParameterOverflow("xmax"));
+    }
+    let _root2n: Integer = (n * Integer::from(2)).sqrt();
 
-    let factorbase: Vec<u64> = primal_sieve::Sieve::new(bound)
-        .primes_from(2)
-        .take_while(|x| x <= &bound)
-        .filter(|x| n.legendre(&Integer::from(*x as u64)) == 1 || *x == 2)
+    let factorbase: Vec<u64> = primal_sieve::Primes::all()
+        .filter(|x| *x == 2 || n.legendre(&Integer::from(*x as u64)) != -1)
         .map(|x| x as u64)
+        .take(tuning.fb_size)
         .collect();
+    let largest = *factorbase.last().unwrap();
+    if largest > u32::MAX as u64 {
+        return Err(FactorError::ParameterOverflow("fb_size"));
+    }
+    if largest.checked_mul(tuning.large_prime_multiplier).is_none() {
+        return Err(FactorError::ParameterOverflow("large_prime_multiplier"));
+    }
 
-    let (mut tsqrt, tlog): (Vec<Integer>, Vec<f64>) = factorbase
+    let (tsqrt, mut tlog): (Vec<u32>, Vec<u8>) = factorbase
         .iter()
-        .map(|p| (tonelli_shanks(&n, &Integer::from(*p)), (*p as f64).log10()))
+        .map(|p| {
+            (
+                sqrt_mod(n.mod_u(*p as u32) as u64, *p) as u32,
+                log2(*p as f64),
+            )
+        })
         .unzip();
-    tsqrt[0] = Integer::new();
+    tlog[0] = two_adic_log(n);
+    let powers = prime_powers(n, &factorbase, &tsqrt);
 
-    let xmax: i64 = factorbase.len() as i64 * 60 * 4;
+    let xmax = tuning.xmax;
     let mval: Integer = (_root2n.clone() * xmax) >> 1;
-    let thresh = mval.to_f64().log10() * 0.735;
-
-    let min_prime = (thresh * 3_f64) as u64;
-    let fudge: f64 = factorbase
-        .iter()
-        .take_while(|p| p < &&(min_prime))
-        .enumerate()
-        .map(|(i, _)| tlog[i])
-        .sum::<f64>()
-        / 4_f64;
-
-    let thresh = thresh - fudge;
+    let thresh = (mval.to_f64().log2() - tuning.thresh_offset).floor();
+    if !(0_f64..=u8::MAX as f64).contains(&thresh) {
+        return Err(FactorError::ParameterOverflow("thresh_offset"));
+    }
+    let thresh = thresh as u8;
 
     let mut roota = (_root2n / xmax).sqrt();
     if roota.is_divisible_2pow(1) {
@@ -172,24 +155,35 @@ pub fn initialize_qs(n: &Integer) -> InitResult {
     }
 
     let roota: Integer = max(roota, Integer::from(3));
-
-    InitResult {
-        roota,
+    let seeder = PolynomialSeeder::new(params.polynomial, n, &factorbase, xmax, roota, params.seed);
+    let bounds = LargePrimeBounds::new(
+        largest,
+        tuning.large_prime_multiplier,
+        tuning.cofactor_exponent,
+    );
+
+    let context = SieveContext {
+        kn: n.clone(),
         factorbase,
         tsqrt,
+        powers,
         xmax,
         tlog,
         thresh,
-        min_prime,
-    }
+        bounds,
+    };
+    Ok(InitResult { context, seeder })
 }
 
 #[cfg(test)]
 mod tests {
     use rug::Integer;
 
+    use crate::control::Control;
     use crate::check_is_divisor;
-    use crate::serial_MPQS::mpqs;
+    use crate::params::Params;
+    use crate::polynomial::PolynomialKind;
+    use crate::serial_MPQS::{initialize_with, mpqs, mpqs_with};
 
     #[test]
     fn test_qs() {
@@ -197,7 +191,7 @@ mod tests {
             .parse::<Integer>()
             .unwrap();
         let ris = "14029308060317546154181".parse::<Integer>().unwrap();
-        check_is_divisor(n, Some(ris));
+        check_is_divisor(n, Ok(ris));
     }
 
     #[test]
@@ -205,4 +199,28 @@ mod tests {
         let n = "9986801107".parse::<Integer>().unwrap();
         check_is_divisor(n.clone(), mpqs(&n));
     }
+
+    #[test]
+    fn test_mpqs_polynomials() {
+        let n = "9986801107".parse::<Integer>().unwrap();
+        let params = Params {
+            polynomial: PolynomialKind::Mpqs,
+            ..Params::default()
+        };
+        let init = initialize_with(&n, &params).unwrap();
+        assert_eq!(init.seeder.kind(), PolynomialKind::Mpqs);
+        check_is_divisor(n.clone(), mpqs_with(&n, init, &Control::default()));
+    }
+
+    #[test]
+    fn test_factor_base_divisor() {
+        let n = "9986801107".parse::<Integer>().unwrap() * 101;
+        assert_eq!(mpqs(&n), Ok(Integer::from(101)));
+
+        let n = "9986801107".parse::<Integer>().unwrap() * 2;
+        assert_eq!(mpqs(&n), Ok(Integer::from(2)));
+
+        let n = Integer::from(1_000_000_007_u64 * 1_000_000_007);
+        assert_eq!(mpqs(&n), Ok(Integer::from(1_000_000_007)));
+    }
 }
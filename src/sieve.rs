@@ -0,0 +1,662 @@
+use rug::Integer;
+
+use crate::polynomial::{inverse_mod, roots_mod, Polynomial};
+use crate::tonelli_shanks::lift_sqrt;
+
+/// Length of a sieve block, one byte per position, small enough to stay in the L1 cache
+pub const BLOCK_SIZE: usize = 1 << 15;
+
+/// Sieve logarithms are in base 2, rounded to the nearest integer
+pub fn log2(x: f64) -> u8 {
+    x.log2().round() as u8
+}
+
+/// Primes below this size are added to the blocks through `Patterns` instead of being walked
+pub const PATTERN_PRIME: u64 = 64;
+
+/// Small primes share a pattern as long as the product of their periods stays below this length
+const PATTERN_PERIOD: usize = 8192;
+
+/// Primes from this size on hit a block at most once per root and are sieved through `Buckets`
+pub const BUCKET_PRIME: u64 = BLOCK_SIZE as u64;
+
+/// Powers of the factor base primes are sieved as long as they stay below this size
+pub const POWER_LIMIT: u64 = BLOCK_SIZE as u64;
+
+/// Root of a prime that divides `a`, such primes are not sieved
+pub const NO_ROOT: u32 = u32::MAX;
+
+/// Product of the periods of primes and powers laid out together, with their `(period, log, sol1, sol2)`
+type PatternGroup = (usize, Vec<(usize, u8, u32, u32)>);
+
+/// Power `p^k`, with `k > 1`, of a factor base prime, sieved with another `log p`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PrimePower {
+    /// Index of `p` in the factor base
+    pub index: usize,
+    pub modulus: u64,
+    /// Square root of `kn` modulo `modulus`
+    pub sqrt: u64,
+}
+
+/// Powers below `POWER_LIMIT` of the odd factor base primes not dividing `n`,
+/// with the square roots in `tsqrt` Hensel-lifted to every power
+pub fn prime_powers(n: &Integer, factorbase: &[u64], tsqrt: &[u32]) -> Vec<PrimePower> {
+    let mut powers = Vec::new();
+    for (index, (p, t)) in factorbase.iter().zip(tsqrt).enumerate() {
+        if p * p >= POWER_LIMIT {
+            break;
+        }
+        if *p == 2 || n.is_divisible_u(*p as u32) {
+            continue;
+        }
+        let (mut modulus, mut sqrt) = (p * p, *t as u64);
+        while modulus < POWER_LIMIT {
+            sqrt = lift_sqrt(n.mod_u(modulus as u32) as u64, sqrt, modulus);
+            powers.push(PrimePower {
+                index,
+                modulus,
+                sqrt,
+            });
+            modulus *= p;
+        }
+    }
+    powers
+}
+
+/// Offsets of the next hits of a prime power in the current block
+struct PowerRoots {
+    modulus: u32,
+    log: u8,
+    sol1: u32,
+    sol2: u32,
+}
+
+/// Offsets of the next hits of every prime in the current block, index-aligned with the factor base,
+/// and of every prime power
+pub struct SieveRoots {
+    pub sol1: Vec<u32>,
+    pub sol2: Vec<u32>,
+    powers: Vec<PowerRoots>,
+}
+
+impl SieveRoots {
+    /// Offsets of the roots of `polynomial` from the start of the interval at `-xmax`
+    pub fn new(
+        polynomial: &Polynomial,
+        factorbase: &[u64],
+        logs: &[u8],
+        powers: &[PrimePower],
+        xmax: i64,
+    ) -> SieveRoots {
+        let offset = |sol: u32, p: u64| {
+            if sol == NO_ROOT {
+                NO_ROOT
+            } else {
+                ((sol as u64 + xmax as u64) % p) as u32
+            }
+        };
+        let (sol1, sol2) = polynomial
+            .roots
+            .iter()
+            .zip(factorbase)
+            .map(|((sol1, sol2), p)| (offset(*sol1, *p), offset(*sol2, *p)))
+            .unzip();
+
+        let powers = powers
+            .iter()
+            .filter_map(|power| {
+                let m = power.modulus;
+                let am = polynomial.a.mod_u(m as u32) as u64;
+                if am.is_multiple_of(factorbase[power.index]) {
+                    return None;
+                }
+                let bm = polynomial.b.mod_u(m as u32) as u64;
+                let (sol1, sol2) = roots_mod(power.sqrt, bm, inverse_mod(am, m), m);
+                Some(PowerRoots {
+                    modulus: m as u32,
+                    log: logs[power.index],
+                    sol1: offset(sol1, m),
+                    sol2: offset(sol2, m),
+                })
+            })
+            .collect();
+        SieveRoots { sol1, sol2, powers }
+    }
+}
+
+/// Logarithms of the primes below `PATTERN_PRIME`, laid out once per polynomial over a whole period
+pub struct Patterns {
+    patterns: Vec<Vec<u8>>,
+}
+
+impl Patterns {
+    /// Groups the small primes and prime powers with roots into patterns
+    /// that start at the beginning of the interval
+    pub fn new(roots: &SieveRoots, factorbase: &[u64], logs: &[u8]) -> Patterns {
+        let small = factorbase.partition_point(|p| *p < PATTERN_PRIME);
+        let primes = (0..small).filter(|i| roots.sol1[*i] != NO_ROOT).map(|i| {
+            (
+                factorbase[i] as usize,
+                logs[i],
+                roots.sol1[i],
+                roots.sol2[i],
+            )
+        });
+        let powers = roots
+            .powers
+            .iter()
+            .filter(|power| (power.modulus as u64) < PATTERN_PRIME)
+            .map(|power| (power.modulus as usize, power.log, power.sol1, power.sol2));
+
+        let mut groups: Vec<PatternGroup> = Vec::new();
+        for (p, logp, sol1, sol2) in primes.chain(powers) {
+            match groups.last_mut() {
+                Some((period, group)) if *period * p <= PATTERN_PERIOD => {
+                    *period *= p;
+                    group.push((p, logp, sol1, sol2));
+                }
+                _ => groups.push((p, vec![(p, logp, sol1, sol2)])),
+            }
+        }
+
+        let patterns = groups
+            .into_iter()
+            .map(|(period, group)| {
+                let mut pattern = vec![0_u8; period];
+                for (p, logp, sol1, sol2) in group {
+                    let sols = if sol1 == sol2 { 1 } else { 2 };
+                    for sol in [sol1, sol2][..sols].iter() {
+                        for x in (*sol as usize..period).step_by(p) {
+                            pattern[x] += logp;
+                        }
+                    }
+                }
+                pattern
+            })
+            .collect();
+        Patterns { patterns }
+    }
+
+    /// Adds the patterns to `block`, which starts at offset `start` of the interval
+    fn add(&self, block: &mut [u8], start: usize) {
+        for pattern in self.patterns.iter() {
+            let mut phase = start % pattern.len();
+            let mut j = 0;
+            while j < block.len() {
+                let len = (pattern.len() - phase).min(block.len() - j);
+                let chunk = block[j..j + len]
+                    .iter_mut()
+                    .zip(&pattern[phase..phase + len]);
+                chunk.for_each(|(b, p)| *b = b.saturating_add(*p));
+                j += len;
+                phase = 0;
+            }
+        }
+    }
+}
+
+/// Hits of the primes from `BUCKET_PRIME` on, sorted by block once per polynomial
+pub struct Buckets {
+    /// Offset in the block and index in the factor base of every hit
+    blocks: Vec<Vec<(u16, u32)>>,
+}
+
+impl Buckets {
+    /// Walks the whole interval of length `len` with every large prime, starting from `roots`
+    pub fn new(roots: &SieveRoots, factorbase: &[u64], len: usize) -> Buckets {
+        let mut blocks = vec![Vec::new(); len.div_ceil(BLOCK_SIZE)];
+        let large = first_bucket_prime(factorbase);
+        for (i, p) in factorbase.iter().enumerate().skip(large) {
+            if roots.sol1[i] == NO_ROOT {
+                continue;
+            }
+            let p = *p as usize;
+            let sols = if roots.sol1[i] == roots.sol2[i] { 1 } else { 2 };
+            for sol in [roots.sol1[i], roots.sol2[i]][..sols].iter() {
+                for x in (*sol as usize..len).step_by(p) {
+                    blocks[x / BLOCK_SIZE].push(((x % BLOCK_SIZE) as u16, i as u32));
+                }
+            }
+        }
+        Buckets { blocks }
+    }
+
+    /// Hits in the `k`-th block of the interval
+    pub fn block(&self, k: usize) -> &[(u16, u32)] {
+        &self.blocks[k]
+    }
+}
+
+/// Index of the first prime sieved through `Buckets`
+fn first_bucket_prime(factorbase: &[u64]) -> usize {
+    factorbase.partition_point(|p| *p < BUCKET_PRIME)
+}
+
+/// Moves the roots of a prime `p` below `PATTERN_PRIME` past a block of length `size`
+fn skip(p: u32, size: u32, sol1: &mut u32, sol2: &mut u32) {
+    *sol1 = (*sol1 + p - size % p) % p;
+    *sol2 = (*sol2 + p - size % p) % p;
+}
+
+/// Adds `logp` at every position of `block` hit by the roots of `p`, then moves them to the next block.
+/// A prime dividing `kn` has a single root, with `sol1 == sol2`, and is added once.
+fn walk(block: &mut [u8], p: u32, logp: u8, sol1: &mut u32, sol2: &mut u32) {
+    let size = block.len() as u32;
+    if *sol1 == *sol2 {
+        while *sol1 < size {
+            block[*sol1 as usize] = block[*sol1 as usize].saturating_add(logp);
+            *sol1 += p;
+        }
+        *sol1 -= size;
+        *sol2 = *sol1;
+        return;
+    }
+    while *sol1 < size || *sol2 < size {
+        if *sol1 < size {
+            block[*sol1 as usize] = block[*sol1 as usize].saturating_add(logp);
+            *sol1 += p;
+        }
+        if *sol2 < size {
+            block[*sol2 as usize] = block[*sol2 as usize].saturating_add(logp);
+            *sol2 += p;
+        }
+    }
+    *sol1 -= size;
+    *sol2 -= size;
+}
+
+/// Clears `block`, which starts at offset `start` of the interval, and adds `logs[i]` at every position
+/// where `factorbase[i]` or one of its powers divides the polynomial, then moves `roots` to the next block.
+/// The small primes are added from `patterns` and the large primes from `bucket`.
+pub fn sieve_block(
+    block: &mut [u8],
+    start: usize,
+    factorbase: &[u64],
+    logs: &[u8],
+    roots: &mut SieveRoots,
+    patterns: &Patterns,
+    bucket: &[(u16, u32)],
+) {
+    for v in block.iter_mut() {
+        *v = 0;
+    }
+    patterns.add(block, start);
+    let size = block.len() as u32;
+
+    let primes = factorbase[..first_bucket_prime(factorbase)]
+        .iter()
+        .zip(logs);
+    for ((p, logp), (sol1, sol2)) in primes.zip(roots.sol1.iter_mut().zip(roots.sol2.iter_mut())) {
+        if *sol1 == NO_ROOT {
+            continue;
+        }
+        let p = *p as u32;
+        if (p as u64) < PATTERN_PRIME {
+            skip(p, size, sol1, sol2);
+        } else {
+            walk(block, p, *logp, sol1, sol2);
+        }
+    }
+    for power in roots.powers.iter_mut() {
+        let m = power.modulus;
+        if (m as u64) < PATTERN_PRIME {
+            skip(m, size, &mut power.sol1, &mut power.sol2);
+        } else {
+            walk(block, m, power.log, &mut power.sol1, &mut power.sol2);
+        }
+    }
+    for (x, i) in bucket {
+        block[*x as usize] = block[*x as usize].saturating_add(logs[*i as usize]);
+    }
+}
+
+/// Offsets in `block` whose sum of logarithms is above `thresh`
+pub fn candidates(block: &[u8], thresh: u8) -> impl Iterator<Item = usize> + '_ {
+    block
+        .iter()
+        .enumerate()
+        .filter(move |(_, v)| **v > thresh)
+        .map(|(i, _)| i)
+}
+
+/// Indices of the factor base primes that divide the polynomial at each of the `candidates` of `block`,
+/// found by walking the block again from the roots left by `sieve_block` and by going through `bucket`.
+/// The primes without roots are always listed and are checked by `divide_out`.
+pub fn resieve(
+    block: &[u8],
+    thresh: u8,
+    candidates: &[usize],
+    factorbase: &[u64],
+    roots: &SieveRoots,
+    bucket: &[(u16, u32)],
+) -> Vec<Vec<usize>> {
+    let mut divisors = vec![Vec::new(); candidates.len()];
+    let size = block.len();
+    let large = first_bucket_prime(factorbase);
+
+    for (i, p) in factorbase.iter().enumerate() {
+        let (sol1, sol2) = (roots.sol1[i], roots.sol2[i]);
+        if sol1 == NO_ROOT {
+            divisors.iter_mut().for_each(|d| d.push(i));
+            continue;
+        }
+        if i >= large {
+            continue;
+        }
+        let p = *p as usize;
+        let start1 = (sol1 as usize + size) % p;
+        let start2 = (sol2 as usize + size) % p;
+
+        if size / p > candidates.len() {
+            for (d, x) in divisors.iter_mut().zip(candidates) {
+                let r = x % p;
+                if r == start1 || r == start2 {
+                    d.push(i);
+                }
+            }
+        } else {
+            for start in [start1, start2].iter() {
+                for x in (*start..size).step_by(p) {
+                    if block[x] > thresh {
+                        divisors[candidates.binary_search(&x).unwrap()].push(i);
+                    }
+                }
+            }
+        }
+    }
+    for (x, i) in bucket {
+        if block[*x as usize] > thresh {
+            divisors[candidates.binary_search(&(*x as usize)).unwrap()].push(*i as usize);
+        }
+    }
+
+    for d in divisors.iter_mut() {
+        d.sort_unstable();
+        d.dedup();
+    }
+    divisors
+}
+
+/// Divides `value` by the primes listed by `resieve`,
+/// returns the cofactor and the exponents of the factor base primes that divide `value`
+pub fn divide_out(
+    value: &Integer,
+    divisors: &[usize],
+    factorbase: &[u64],
+) -> (Integer, Vec<(usize, u32)>) {
+    let mut nf = value.clone().abs();
+    let mut exponents = Vec::new();
+    for i in divisors {
+        let p = factorbase[*i] as u32;
+        let mut e = 0;
+        while nf.is_divisible_u(p) {
+            nf.div_exact_u_mut(p);
+            e += 1;
+        }
+        if e > 0 {
+            exponents.push((*i, e));
+        }
+    }
+    (nf, exponents)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::polynomial::Family;
+    use crate::serial_MPQS::{initialize_qs, InitResult};
+
+    use super::*;
+
+    fn polynomial(roots: &[(u32, u32)]) -> Polynomial {
+        Polynomial {
+            a: Integer::from(1),
+            b: Integer::from(0),
+            c: Integer::from(0),
+            ra: Integer::from(1),
+            rhs_factors: Vec::new(),
+            roots: roots.to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_sieve_block() {
+        let factorbase = [5_u64, 7, 11, 13];
+        let logs: Vec<u8> = factorbase.iter().map(|p| log2(*p as f64)).collect();
+        let roots = [(NO_ROOT, NO_ROOT), (1, 5), (2, 2), (3, 3)];
+        let mut roots = SieveRoots::new(&polynomial(&roots), &factorbase, &logs, &[], 0);
+        let mut block = vec![0_u8; 50];
+
+        let patterns = Patterns::new(&roots, &factorbase, &logs);
+        sieve_block(
+            &mut block,
+            0,
+            &factorbase,
+            &logs,
+            &mut roots,
+            &patterns,
+            &[],
+        );
+        assert_eq!(block[0], 0);
+        assert_eq!(block[1], 3);
+        assert_eq!(block[2], 3);
+        assert_eq!(block[29], 3 + 4);
+        assert_eq!(candidates(&block, 3).collect::<Vec<_>>(), [3, 16, 29, 42]);
+        assert_eq!(roots.sol1, [NO_ROOT, 0, 7, 5]);
+        assert_eq!(roots.sol2[1], 4);
+    }
+
+    #[test]
+    fn test_resieve() {
+        let factorbase = [2_u64, 5, 7, 11, 13];
+        let logs: Vec<u8> = factorbase.iter().map(|p| log2(*p as f64)).collect();
+        let roots = [(1, 1), (NO_ROOT, NO_ROOT), (1, 5), (2, 2), (3, 3)];
+        let mut roots = SieveRoots::new(&polynomial(&roots), &factorbase, &logs, &[], 0);
+        let mut block = vec![0_u8; 50];
+
+        let patterns = Patterns::new(&roots, &factorbase, &logs);
+        sieve_block(
+            &mut block,
+            0,
+            &factorbase,
+            &logs,
+            &mut roots,
+            &patterns,
+            &[],
+        );
+        let candidates: Vec<usize> = candidates(&block, 3).collect();
+        assert_eq!(
+            candidates,
+            [1, 3, 5, 13, 15, 16, 19, 29, 33, 35, 42, 43, 47]
+        );
+        let divisors = resieve(&block, 3, &candidates, &factorbase, &roots, &[]);
+        assert_eq!(divisors[5], [1, 4]);
+        assert_eq!(divisors[7], [0, 1, 2, 4]);
+        assert_eq!(divisors[9], [0, 1, 3]);
+
+        let (nf, exponents) = divide_out(
+            &Integer::from(-2 * 7 * 7 * 13 * 17),
+            &divisors[7],
+            &factorbase,
+        );
+        assert_eq!(nf, 17);
+        assert_eq!(exponents, [(0, 1), (2, 2), (4, 1)]);
+    }
+
+    #[test]
+    fn test_prime_powers() {
+        // x^2 - 10, sieved from x = 0
+        let n = Integer::from(10);
+        let factorbase = [3_u64];
+        let logs = [log2(3.0)];
+        let tsqrt = [1];
+        let powers = prime_powers(&n, &factorbase, &tsqrt);
+        assert_eq!(powers.len(), 8);
+        for power in powers.iter() {
+            assert_eq!((power.sqrt.pow(2) + power.modulus - 10) % power.modulus, 0);
+        }
+
+        let mut polynomial = polynomial(&[(1, 2)]);
+        polynomial.c = -n;
+        let mut roots = SieveRoots::new(&polynomial, &factorbase, &logs, &powers, 0);
+        let patterns = Patterns::new(&roots, &factorbase, &logs);
+        let mut block = vec![0_u8; 200];
+        sieve_block(
+            &mut block,
+            0,
+            &factorbase,
+            &logs,
+            &mut roots,
+            &patterns,
+            &[],
+        );
+        for (x, v) in block.iter().enumerate() {
+            let mut value = Integer::from((x * x) as i64 - 10);
+            let mut e = 0;
+            while value.is_divisible_u(3) {
+                value /= 3;
+                e += 1;
+            }
+            assert_eq!(*v, logs[0] * e, "x = {}", x);
+        }
+    }
+
+    #[test]
+    fn test_buckets() {
+        let factorbase = [3_u64, 32771, 40009];
+        let logs: Vec<u8> = factorbase.iter().map(|p| log2(*p as f64)).collect();
+        let roots = [(1, 2), (5, 100), (7, 7)];
+        let mut roots = SieveRoots::new(&polynomial(&roots), &factorbase, &logs, &[], 0);
+        let len = 2 * BLOCK_SIZE + 1000;
+        let patterns = Patterns::new(&roots, &factorbase, &logs);
+        let buckets = Buckets::new(&roots, &factorbase, len);
+
+        let mut hits = Vec::new();
+        let mut block = vec![0_u8; BLOCK_SIZE];
+        for (k, low) in (0..len).step_by(BLOCK_SIZE).enumerate() {
+            let block = &mut block[..BLOCK_SIZE.min(len - low)];
+            sieve_block(
+                block,
+                low,
+                &factorbase,
+                &logs,
+                &mut roots,
+                &patterns,
+                buckets.block(k),
+            );
+            let candidates: Vec<usize> = candidates(block, 14).collect();
+            let divisors = resieve(
+                block,
+                14,
+                &candidates,
+                &factorbase,
+                &roots,
+                buckets.block(k),
+            );
+            if k == 1 {
+                // 40009 has a single root and is added once
+                assert_eq!(block[40016 - BLOCK_SIZE], 2 + 15);
+            }
+            hits.extend(candidates.into_iter().map(|x| low + x).zip(divisors));
+        }
+        let expected: Vec<(usize, Vec<usize>)> = vec![
+            (5, vec![0, 1]),
+            (7, vec![0, 2]),
+            (100, vec![0, 1]),
+            (32776, vec![0, 1]),
+            (32871, vec![1]),
+            (40016, vec![0, 2]),
+            (65547, vec![1]),
+            (65642, vec![0, 1]),
+        ];
+        assert_eq!(hits, expected);
+    }
+
+    /// The byte sieve against exact `f64` logarithms over the same primes and powers:
+    /// every hit is off by at most half a bit, so the positions whose exact sum is further
+    /// than that from `thresh` are on the same side of it in both sieves
+    #[test]
+    fn test_rounding() {
+        let n = "523022617466601111760007224100074291200000001"
+            .parse::<Integer>()
+            .unwrap();
+        let InitResult {
+            context,
+            mut seeder,
+        } = initialize_qs(&n).unwrap();
+        let seed = seeder.next_seed(&context.kn, &context.factorbase);
+        let polynomial = Family::new(&context.kn, &context.factorbase, &context.tsqrt, seed)
+            .next()
+            .unwrap();
+        let (factorbase, tlog, xmax) = (&context.factorbase, &context.tlog, context.xmax);
+        let len = 2 * xmax as usize + 1;
+
+        // Exact sum of logarithms and number of hits at every position
+        let mut exact = vec![(0_f64, 0_u32); len];
+        let mut roots = SieveRoots::new(&polynomial, factorbase, tlog, &context.powers, xmax);
+        let mut add = |p: usize, logp: f64, sol1: u32, sol2: u32| {
+            let sols = if sol1 == sol2 { 1 } else { 2 };
+            for sol in [sol1, sol2][..sols].iter() {
+                for x in (*sol as usize..len).step_by(p) {
+                    exact[x].0 += logp;
+                    exact[x].1 += 1;
+                }
+            }
+        };
+        for (i, p) in factorbase.iter().enumerate() {
+            if roots.sol1[i] != NO_ROOT {
+                // The logarithm of 2 is the exponent of 2, which needs no rounding
+                let logp = if i == 0 {
+                    tlog[0] as f64
+                } else {
+                    (*p as f64).log2()
+                };
+                add(*p as usize, logp, roots.sol1[i], roots.sol2[i]);
+            }
+        }
+        for power in roots.powers.iter() {
+            let p = factorbase
+                .iter()
+                .find(|p| (power.modulus as u64).is_multiple_of(**p))
+                .unwrap();
+            add(
+                power.modulus as usize,
+                (*p as f64).log2(),
+                power.sol1,
+                power.sol2,
+            );
+        }
+
+        let patterns = Patterns::new(&roots, factorbase, tlog);
+        let buckets = Buckets::new(&roots, factorbase, len);
+        let mut block = vec![0_u8; BLOCK_SIZE];
+        let mut differences = 0;
+        for (k, low) in (0..len).step_by(BLOCK_SIZE).enumerate() {
+            let block = &mut block[..BLOCK_SIZE.min(len - low)];
+            sieve_block(
+                block,
+                low,
+                factorbase,
+                tlog,
+                &mut roots,
+                &patterns,
+                buckets.block(k),
+            );
+            for (x, v) in block.iter().enumerate() {
+                let (sum, hits) = exact[low + x];
+                let tolerance = hits as f64 / 2_f64;
+                assert!((*v as f64 - sum).abs() <= tolerance);
+                let thresh = context.thresh as f64;
+                if sum > thresh + tolerance {
+                    assert!(*v > context.thresh);
+                } else if sum + tolerance <= thresh {
+                    assert!(*v <= context.thresh);
+                } else if (*v > context.thresh) != (sum > thresh) {
+                    differences += 1;
+                }
+            }
+        }
+        let found = exact.iter().filter(|(sum, _)| *sum > context.thresh as f64);
+        assert!(differences < found.count());
+    }
+}
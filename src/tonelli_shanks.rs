@@ -1,5 +1,7 @@
 use rug::Integer;
 
+use crate::polynomial::inverse_mod;
+
 /// Tonelli Shanks Algorithm
 pub fn tonelli_shanks(n: &Integer, p: &Integer) -> Integer {
     if n.legendre(p) != 1 || *n == 0 {
@@ -50,6 +52,66 @@ pub fn tonelli_shanks(n: &Integer, p: &Integer) -> Integer {
     }
 }
 
+/// Tonelli Shanks on machine words, `n` must be a square modulo the prime `p`
+pub fn sqrt_mod(n: u64, p: u64) -> u64 {
+    let n = n % p;
+    if p == 2 || n == 0 {
+        return n;
+    }
+    let mul = |a: u64, b: u64| (a as u128 * b as u128 % p as u128) as u64;
+    let pow = |mut base: u64, mut exp: u64| {
+        let mut result = 1;
+        while exp > 0 {
+            if exp & 1 == 1 {
+                result = mul(result, base);
+            }
+            base = mul(base, base);
+            exp >>= 1;
+        }
+        result
+    };
+    if p % 4 == 3 {
+        return pow(n, (p + 1) / 4);
+    }
+
+    let e = (p - 1).trailing_zeros();
+    let s = (p - 1) >> e;
+    let mut z = 2;
+    while pow(z, (p - 1) / 2) != p - 1 {
+        z += 1;
+    }
+
+    let mut b = pow(n, s);
+    let mut g = pow(z, s);
+    let mut x = pow(n, s.div_ceil(2));
+    let mut r = e;
+    loop {
+        let mut t = b;
+        let mut m = 0;
+        while m < r && t != 1 {
+            t = mul(t, t);
+            m += 1;
+        }
+        if m == 0 {
+            return x;
+        }
+
+        let gs = pow(g, 1 << (r - m - 1));
+        g = mul(gs, gs);
+        x = mul(x, gs);
+        b = mul(b, g);
+        r = m;
+    }
+}
+
+/// Hensel lifting of a square root: given `t^2 = n` modulo `m / p` for an odd prime `p` not dividing `n`,
+/// returns the square root of `n` modulo `m` congruent to `t`
+pub fn lift_sqrt(n: u64, t: u64, m: u64) -> u64 {
+    let n = n % m;
+    let error = (t * t % m + m - n) % m;
+    (t + m - error * inverse_mod(2 * t % m, m) % m) % m
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -58,5 +120,18 @@ mod tests {
     fn test_tonelli_shank() {
         let (n, p) = (Integer::from(23479349), Integer::from(23));
         assert_eq!(tonelli_shanks(&n, &p), Integer::from(12));
+        assert_eq!(sqrt_mod(23479349, 23), 12);
+
+        let p = 40961;
+        for n in [3_u64, 5, 10, 1234].iter() {
+            let r = sqrt_mod(n * n, p);
+            assert!(r == n % p || r == p - n % p);
+        }
+
+        let mut t = sqrt_mod(10, 3);
+        for m in [9_u64, 27, 81, 243].iter() {
+            t = lift_sqrt(10, t, *m);
+            assert_eq!(t * t % m, 10 % m);
+        }
     }
 }